@@ -0,0 +1,27 @@
+//! Exposes the tlsn release this host is built against as `TLSN_VERSION`, read
+//! from the `tlsn-prover` dependency in Cargo.toml so the two cannot drift.
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    let manifest = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest.display());
+
+    let contents = fs::read_to_string(&manifest).expect("failed to read Cargo.toml");
+    let tag = contents
+        .lines()
+        .find(|line| line.trim_start().starts_with("tlsn-prover"))
+        .and_then(|line| attribute(line, "tag"))
+        .expect("Cargo.toml has no `tag` for the tlsn-prover dependency");
+
+    println!(
+        "cargo:rustc-env=TLSN_VERSION={}",
+        tag.strip_prefix('v').unwrap_or(tag)
+    );
+}
+
+/// Value of `name = "..."` in an inline dependency table.
+fn attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(&format!("{} = \"", name))?;
+    rest.split('"').next()
+}
@@ -1,5 +1,8 @@
 use pest::{iterators::Pair, RuleType};
-use std::{collections::HashMap, ops::Range};
+use std::ops::Range;
+
+use crate::keypath::Keypath;
+use crate::policy::Occurrence;
 
 #[derive(Debug, Clone, Default)]
 pub struct RangedHeader {
@@ -9,14 +12,18 @@ pub struct RangedHeader {
 
 #[derive(Debug, Clone)]
 pub enum RangedValue {
-    Null,
+    Null {
+        range: Range<usize>,
+    },
     Bool {
         range: Range<usize>,
         value: bool,
     },
     Number {
         range: Range<usize>,
-        value: f64,
+        /// The number exactly as written, so large ids and amounts are not
+        /// rounded through a float.
+        value: String,
     },
     String {
         range: Range<usize>,
@@ -28,9 +35,74 @@ pub enum RangedValue {
     },
     Object {
         range: Range<usize>,
-        value: HashMap<String, RangedValue>,
+        value: MultiMap<RangedMember>,
     },
 }
+
+/// A member of a JSON object: its value together with the range of its key.
+#[derive(Debug, Clone)]
+pub struct RangedMember {
+    /// Range of the key including its quotes.
+    pub key_range: Range<usize>,
+    pub value: RangedValue,
+}
+
+/// Entries in the order they were parsed. Unlike a map, a key may occur more
+/// than once, as with repeated headers or duplicate JSON keys.
+#[derive(Debug, Clone)]
+pub struct MultiMap<V> {
+    entries: Vec<(String, V)>,
+}
+
+impl<V> Default for MultiMap<V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<V> MultiMap<V> {
+    pub fn push(&mut self, key: String, value: V) {
+        self.entries.push((key, value));
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    /// Returns the `occurrence` of the entries stored under `key`.
+    pub fn get(&self, key: &str, occurrence: Occurrence) -> Vec<&V> {
+        self.get_matching(|entry_key| entry_key == key, occurrence)
+    }
+
+    /// Like `get`, but compares keys ignoring ASCII case, as header names are.
+    pub fn get_ignore_ascii_case(&self, key: &str, occurrence: Occurrence) -> Vec<&V> {
+        self.get_matching(|entry_key| entry_key.eq_ignore_ascii_case(key), occurrence)
+    }
+
+    fn get_matching(&self, matches: impl Fn(&str) -> bool, occurrence: Occurrence) -> Vec<&V> {
+        let mut found = self
+            .entries
+            .iter()
+            .filter(|(entry_key, _)| matches(entry_key))
+            .map(|(_, value)| value);
+        match occurrence {
+            Occurrence::First => found.next().into_iter().collect(),
+            Occurrence::Last => found.last().into_iter().collect(),
+            Occurrence::All => found.collect(),
+        }
+    }
+}
+
+impl<V> FromIterator<(String, V)> for MultiMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(entries: I) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+        }
+    }
+}
+
 impl Default for RangedValue {
     fn default() -> Self {
         RangedValue::Object {
@@ -44,8 +116,8 @@ impl RangedValue {
     /// Get the range of the current RangedValue.
     pub fn get_range(&self) -> Range<usize> {
         match self {
-            RangedValue::Null => 0..0, // Or handle it differently if needed
-            RangedValue::Bool { range, .. }
+            RangedValue::Null { range }
+            | RangedValue::Bool { range, .. }
             | RangedValue::Number { range, .. }
             | RangedValue::String { range, .. }
             | RangedValue::Array { range, .. }
@@ -92,113 +164,224 @@ impl CommonParser {
         Ok((key, RangedHeader { range, value }))
     }
 
-    pub fn parse_value<R: CommonRule>(pair: Pair<R>) -> RangedValue {
+    pub fn parse_value<R: CommonRule>(pair: Pair<R>) -> Result<RangedValue, &'static str> {
         let range = pair.as_span().start()..pair.as_span().end();
 
-        match pair.as_rule().rule_type() {
+        let value = match pair.as_rule().rule_type() {
             CommonRuleType::Object => RangedValue::Object {
                 range,
                 value: pair
                     .into_inner()
                     .map(|p| Self::parse_object_entry(p))
-                    .collect::<HashMap<_, _>>(),
+                    .collect::<Result<MultiMap<_>, _>>()?,
             },
             CommonRuleType::Array => RangedValue::Array {
                 range,
-                value: pair.into_inner().map(|p| Self::parse_value(p)).collect(),
+                value: pair
+                    .into_inner()
+                    .map(|p| Self::parse_value(p))
+                    .collect::<Result<_, _>>()?,
             },
             CommonRuleType::String => RangedValue::String {
                 range,
-                value: pair.into_inner().next().unwrap().as_str().to_string(),
+                value: unescape(
+                    pair.into_inner()
+                        .next()
+                        .ok_or("Missing string contents")?
+                        .as_str(),
+                ),
             },
             CommonRuleType::Number => RangedValue::Number {
                 range,
-                value: pair.as_str().parse().unwrap_or_default(),
+                value: pair.as_str().to_string(),
             },
             CommonRuleType::Boolean => RangedValue::Bool {
                 range,
-                value: pair.as_str().parse().unwrap_or_default(),
+                value: pair.as_str().parse().map_err(|_| "Invalid boolean")?,
             },
-            CommonRuleType::Null => RangedValue::Null,
-            CommonRuleType::Other => unreachable!("Unexpected rule in parse_value"),
-        }
+            CommonRuleType::Null => RangedValue::Null { range },
+            CommonRuleType::Other => return Err("Unexpected rule in JSON value"),
+        };
+        Ok(value)
     }
 
-    fn parse_object_entry<R: CommonRule>(pair: Pair<R>) -> (String, RangedValue) {
+    fn parse_object_entry<R: CommonRule>(
+        pair: Pair<R>,
+    ) -> Result<(String, RangedMember), &'static str> {
         let mut inner_rules = pair.into_inner();
-        let key = inner_rules
-            .next()
-            .expect("Missing key in object entry")
-            .into_inner()
-            .next()
-            .expect("Missing key in object entry")
-            .as_str()
-            .to_string();
-        let value = Self::parse_value(inner_rules.next().expect("Missing value in object entry"));
-        (key, value)
+        let key_pair = inner_rules.next().ok_or("Missing key in object entry")?;
+        let key_range = key_pair.as_span().start()..key_pair.as_span().end();
+        let key = unescape(
+            key_pair
+                .into_inner()
+                .next()
+                .ok_or("Missing key in object entry")?
+                .as_str(),
+        );
+        let value = Self::parse_value(inner_rules.next().ok_or("Missing value in object entry")?)?;
+        Ok((key, RangedMember { key_range, value }))
+    }
+}
+
+/// Decodes the escape sequences in the contents of a JSON string. The grammar
+/// has already checked them; unpaired surrogates decode to U+FFFD.
+fn unescape(raw: &str) -> String {
+    if !raw.contains('\\') {
+        return raw.to_string();
+    }
+
+    let mut decoded = String::with_capacity(raw.len());
+    // UTF-16 code units of consecutive `\u` escapes, decoded together so
+    // surrogate pairs combine.
+    let mut units = Vec::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    units.push(u16::from_str_radix(&hex, 16).unwrap_or(0xFFFD));
+                    continue;
+                }
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                // `\"`, `\\` and `\/` stand for the character itself.
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        };
+        decode_utf16(&mut units, &mut decoded);
+        decoded.push(c);
     }
+    decode_utf16(&mut units, &mut decoded);
+    decoded
+}
+
+fn decode_utf16(units: &mut Vec<u16>, decoded: &mut String) {
+    decoded.extend(
+        char::decode_utf16(units.drain(..)).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+    );
 }
 
 pub trait Searchable {
-    fn get_headers(&self) -> &HashMap<String, RangedHeader>;
+    fn get_headers(&self) -> &MultiMap<RangedHeader>;
     fn get_content(&self) -> Option<&RangedValue>;
     fn get_additional_ranges(&self) -> Vec<Range<usize>> {
         Vec::new()
     }
+    /// Maps a range of the parsed content to transcript ranges. Content parsed
+    /// straight from the transcript needs no mapping.
+    fn map_content_range(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        vec![range]
+    }
+
+    /// Returns the names in `headers` that no header of the message has.
+    fn missing_headers(&self, headers: &[String]) -> Vec<String> {
+        headers
+            .iter()
+            .filter(|name| {
+                self.get_headers()
+                    .get_ignore_ascii_case(name, Occurrence::First)
+                    .is_empty()
+            })
+            .cloned()
+            .collect()
+    }
 
     fn get_all_ranges_for_keypaths(
         &self,
-        keypaths: &[&str],
-        headers: &[&str],
+        keypaths: &[Keypath],
+        headers: &[String],
+        occurrence: Occurrence,
     ) -> Vec<Range<usize>> {
         let mut ranges = Vec::new();
 
         // Add any additional ranges specific to the type
         ranges.extend(self.get_additional_ranges());
 
-        // Check headers for matching keys
-        for (key, header) in self.get_headers() {
-            if headers.contains(&key.as_str()) {
-                ranges.push(header.range.clone());
-            }
+        // Check headers for matching keys, ignoring case as HTTP does
+        for name in headers {
+            let found = self.get_headers().get_ignore_ascii_case(name, occurrence);
+            ranges.extend(found.into_iter().map(|header| header.range.clone()));
         }
 
-        // Recursively search in content for matching key paths
+        // Collect the values selected by the key paths
         if let Some(content) = self.get_content() {
-            Self::search_content_by_path(keypaths, content, Vec::new(), &mut ranges);
+            let content_ranges = keypaths.iter().flat_map(|keypath| {
+                keypath
+                    .select(content, occurrence)
+                    .into_iter()
+                    .filter_map(|selected| selected.disclosed_range(keypath.disclosure()))
+            });
+            ranges.extend(content_ranges.flat_map(|range| self.map_content_range(range)));
         }
 
         ranges
     }
+}
 
-    fn search_content_by_path(
-        keypaths: &[&str],
-        content: &RangedValue,
-        current_path: Vec<String>,
-        ranges: &mut Vec<Range<usize>>,
-    ) {
-        match content {
-            RangedValue::Object { value, .. } => {
-                for (key, val) in value {
-                    let mut new_path = current_path.clone();
-                    new_path.push(key.clone());
-                    let path_str = new_path.join(".");
-
-                    if keypaths.contains(&path_str.as_str()) {
-                        let start = val.get_range().start;
-                        let end = val.get_range().end;
-                        ranges.push((start - key.len() - 3)..end);
-                    }
-                    Self::search_content_by_path(keypaths, val, new_path, ranges);
-                }
-            }
-            RangedValue::Array { value, .. } => {
-                for item in value {
-                    Self::search_content_by_path(keypaths, item, current_path.clone(), ranges);
-                }
-            }
-            _ => {}
-        }
+#[cfg(test)]
+mod tests {
+    use pest::Parser;
+
+    use super::*;
+    use crate::response::{parse_response_value, ResponseParser, Rule};
+
+    #[test]
+    fn unescapes_simple_escapes() {
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(
+            unescape(r#"a\"b\\c\/d\b\f\n\r\t"#),
+            "a\"b\\c/d\u{8}\u{c}\n\r\t"
+        );
+        assert_eq!(unescape(r"caf\u00e9 \u00E9"), "café é");
+    }
+
+    #[test]
+    fn combines_surrogate_pairs() {
+        assert_eq!(unescape(r"\ud83d\ude00"), "😀");
+        assert_eq!(unescape(r"a\ud83d\ude00b\u00e9"), "a😀bé");
+    }
+
+    #[test]
+    fn replaces_lone_surrogates() {
+        assert_eq!(unescape(r"\ud83dx"), "\u{FFFD}x");
+        assert_eq!(unescape(r"\ude00"), "\u{FFFD}");
+        assert_eq!(unescape(r"\ud83d\u0041"), "\u{FFFD}A");
+        assert_eq!(unescape(r"\ud83d\ud83d\ude00"), "\u{FFFD}😀");
+        assert_eq!(unescape(r"\ud83d\n"), "\u{FFFD}\n");
+    }
+
+    #[test]
+    fn decodes_keys_and_strings_but_keeps_raw_ranges() {
+        let json = r#"{"caf\u00e9":"a\"b","Caf\u00e9":"\ud83d\ude00"}"#;
+        let pair = ResponseParser::parse(Rule::json_body, json)
+            .unwrap()
+            .next()
+            .unwrap();
+        let RangedValue::Object { value: members, .. } = parse_response_value(pair).unwrap() else {
+            panic!("expected an object");
+        };
+
+        let member = members.get("café", Occurrence::All);
+        assert_eq!(member.len(), 1);
+        assert_eq!(&json[member[0].key_range.clone()], r#""caf\u00e9""#);
+        let RangedValue::String { range, value } = &member[0].value else {
+            panic!("expected a string");
+        };
+        assert_eq!(value, "a\"b");
+        assert_eq!(&json[range.clone()], r#""a\"b""#);
+
+        // Object keys are compared exactly, unlike header names.
+        assert_eq!(members.get("Café", Occurrence::All).len(), 1);
+        assert_eq!(
+            members.get_ignore_ascii_case("CAFé", Occurrence::All).len(),
+            2
+        );
     }
 }
 
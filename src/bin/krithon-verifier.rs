@@ -0,0 +1,238 @@
+use std::net::SocketAddr;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::Parser;
+use serde::Serialize;
+use thiserror::Error;
+use tlsn_common::config::{ProtocolConfigValidator, ProtocolConfigValidatorBuilderError};
+use tlsn_core::{
+    attestation::AttestationConfig, connection::ServerName, signing::SignatureAlgId, CryptoProvider,
+};
+use tlsn_verifier::{Verifier, VerifierConfig, VerifierConfigBuilderError, VerifierError};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+/// Verifier side of the MPC-TLS protocol, for running krithon-prover
+/// end-to-end without an external verifier.
+#[derive(Parser, Debug, Clone)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "127.0.0.1:8079")]
+    listen_address: SocketAddr,
+
+    #[arg(long, default_value = "4096")]
+    max_sent_data: usize,
+
+    #[arg(long, default_value = "16384")]
+    max_recv_data: usize,
+
+    /// Byte written in place of every redacted byte of the transcript.
+    #[arg(long, default_value = "X")]
+    redaction_marker: char,
+
+    /// Print each session as a single JSON line instead of plain text.
+    #[arg(long)]
+    json: bool,
+
+    /// Exit after the first session.
+    #[arg(long)]
+    once: bool,
+
+    /// Act as a notary: sign an attestation instead of verifying interactively.
+    #[arg(long)]
+    notarize: bool,
+
+    /// Hex-encoded secp256k1 key the notary signs attestations with.
+    /// Only meant for local development.
+    #[arg(long, env = "KRITHON_NOTARY_KEY", required_if_eq("notarize", "true"))]
+    signing_key: Option<String>,
+}
+
+#[derive(Error, Debug)]
+enum VerifierErrors {
+    #[error("Redaction marker must be an ASCII character")]
+    InvalidRedactionMarker,
+
+    #[error("Invalid signing key: {0}")]
+    InvalidSigningKey(String),
+
+    #[error("Invalid attestation config: {0}")]
+    AttestationConfigError(String),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    ProtocolConfigValidatorBuilderError(#[from] ProtocolConfigValidatorBuilderError),
+
+    #[error(transparent)]
+    VerifierConfigBuilderError(#[from] VerifierConfigBuilderError),
+
+    #[error(transparent)]
+    VerifierError(#[from] VerifierError),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    BincodeError(#[from] bincode::Error),
+}
+
+/// What the verifier learned from one session.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Session {
+    Verified {
+        peer: SocketAddr,
+        server_name: String,
+        sent: String,
+        received: String,
+    },
+    Notarized {
+        peer: SocketAddr,
+        /// Bincode-serialized attestation, base64-encoded.
+        attestation: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), VerifierErrors> {
+    let args = Args::parse();
+    if !args.redaction_marker.is_ascii() {
+        return Err(VerifierErrors::InvalidRedactionMarker);
+    }
+
+    let listener = TcpListener::bind(args.listen_address).await?;
+    eprintln!("Verifier listening on {}", listener.local_addr()?);
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        eprintln!("Prover connected from {}", peer);
+
+        if args.once {
+            let session = run_session(socket, peer, &args).await?;
+            print_session(&session, args.json)?;
+            return Ok(());
+        }
+
+        let args = args.clone();
+        tokio::spawn(async move {
+            match run_session(socket, peer, &args).await {
+                Ok(session) => {
+                    if let Err(e) = print_session(&session, args.json) {
+                        eprintln!("Failed to print session from {}: {}", peer, e);
+                    }
+                }
+                Err(e) => eprintln!("Session from {} failed: {}", peer, e),
+            }
+        });
+    }
+}
+
+async fn run_session(
+    socket: TcpStream,
+    peer: SocketAddr,
+    args: &Args,
+) -> Result<Session, VerifierErrors> {
+    // The limits must match what the prover asks for in its `ProtocolConfig`.
+    let config_validator = ProtocolConfigValidator::builder()
+        .max_sent_data(args.max_sent_data)
+        .max_recv_data(args.max_recv_data)
+        .build()?;
+
+    match &args.signing_key {
+        Some(signing_key) if args.notarize => {
+            notarize(socket, peer, config_validator, signing_key).await
+        }
+        _ => verify(socket, peer, config_validator, args.redaction_marker).await,
+    }
+}
+
+/// Runs the verifier side of one MPC-TLS session and returns the transcript
+/// the prover chose to reveal.
+async fn verify(
+    socket: TcpStream,
+    peer: SocketAddr,
+    config_validator: ProtocolConfigValidator,
+    redaction_marker: char,
+) -> Result<Session, VerifierErrors> {
+    let verifier_config = VerifierConfig::builder()
+        .protocol_config_validator(config_validator)
+        .build()?;
+
+    let verifier = Verifier::new(verifier_config);
+    let (mut partial_transcript, session_info) = verifier.verify(socket.compat()).await?;
+
+    // Checked to be ASCII in `main`.
+    partial_transcript.set_unauthed(redaction_marker as u8);
+
+    let ServerName::Dns(server_name) = session_info.server_name;
+
+    Ok(Session::Verified {
+        peer,
+        server_name,
+        sent: String::from_utf8_lossy(partial_transcript.sent_unsafe()).to_string(),
+        received: String::from_utf8_lossy(partial_transcript.received_unsafe()).to_string(),
+    })
+}
+
+/// Runs the notary side of one MPC-TLS session and returns the signed attestation.
+async fn notarize(
+    socket: TcpStream,
+    peer: SocketAddr,
+    config_validator: ProtocolConfigValidator,
+    signing_key: &str,
+) -> Result<Session, VerifierErrors> {
+    let signing_key =
+        hex::decode(signing_key).map_err(|e| VerifierErrors::InvalidSigningKey(e.to_string()))?;
+
+    let mut provider = CryptoProvider::default();
+    provider
+        .signer
+        .set_secp256k1(&signing_key)
+        .map_err(|e| VerifierErrors::InvalidSigningKey(e.to_string()))?;
+
+    let verifier_config = VerifierConfig::builder()
+        .protocol_config_validator(config_validator)
+        .crypto_provider(provider)
+        .build()?;
+
+    let attestation_config = AttestationConfig::builder()
+        .supported_signature_algorithms(vec![SignatureAlgId::SECP256K1])
+        .build()
+        .map_err(|e| VerifierErrors::AttestationConfigError(e.to_string()))?;
+
+    let attestation = Verifier::new(verifier_config)
+        .notarize(socket.compat(), &attestation_config)
+        .await?;
+
+    Ok(Session::Notarized {
+        peer,
+        attestation: STANDARD.encode(bincode::serialize(&attestation)?),
+    })
+}
+
+fn print_session(session: &Session, json: bool) -> Result<(), VerifierErrors> {
+    if json {
+        println!("{}", serde_json::to_string(session)?);
+        return Ok(());
+    }
+
+    match session {
+        Session::Verified {
+            peer,
+            server_name,
+            sent,
+            received,
+        } => {
+            println!("Verified session with {} from {}", server_name, peer);
+            println!("Sent:\n{}\n", sent);
+            println!("Received:\n{}\n", received);
+        }
+        Session::Notarized { peer, attestation } => {
+            println!("Notarized session from {}", peer);
+            println!("Attestation:\n{}\n", attestation);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use std::ops::Range;
+
+use crate::ast::{MultiMap, RangedHeader};
+use crate::policy::Occurrence;
+
+/// A contiguous run of body bytes and where it sits in the transcript.
+#[derive(Debug, Clone)]
+struct Segment {
+    body_start: usize,
+    transcript_start: usize,
+    len: usize,
+}
+
+/// Maps offsets of a decoded HTTP body back to offsets in the transcript,
+/// skipping the chunk-size lines of a chunked body.
+#[derive(Debug, Clone, Default)]
+pub struct BodyMap {
+    segments: Vec<Segment>,
+}
+
+impl BodyMap {
+    /// Translates a range of the decoded body into the transcript ranges that
+    /// hold the same bytes. A range spanning a chunk boundary is split so the
+    /// chunk framing in between is left out.
+    pub fn to_transcript(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        self.segments
+            .iter()
+            .filter_map(|segment| {
+                let start = range.start.max(segment.body_start);
+                let end = range.end.min(segment.body_start + segment.len);
+                (start < end).then(|| {
+                    segment.transcript_start + (start - segment.body_start)
+                        ..segment.transcript_start + (end - segment.body_start)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Decodes the message body that starts at `offset` in the transcript according
+/// to `Transfer-Encoding: chunked` or `Content-Length`, falling back to reading
+/// until the end of the transcript.
+pub fn decode_body(
+    raw: &[u8],
+    offset: usize,
+    headers: &MultiMap<RangedHeader>,
+) -> Result<(Vec<u8>, BodyMap), &'static str> {
+    let chunked = headers
+        .get_ignore_ascii_case("transfer-encoding", Occurrence::All)
+        .into_iter()
+        .flat_map(|header| header.value.split(','))
+        .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+    if chunked {
+        return decode_chunked(raw, offset);
+    }
+
+    let len = match content_length(headers)? {
+        Some(len) if len > raw.len() => {
+            return Err("Response body is shorter than Content-Length");
+        }
+        Some(len) => len,
+        None => raw.len(),
+    };
+
+    let map = BodyMap {
+        segments: vec![Segment {
+            body_start: 0,
+            transcript_start: offset,
+            len,
+        }],
+    };
+    Ok((raw[..len].to_vec(), map))
+}
+
+/// Reads the `Content-Length` of the message. Repeated fields must agree, as
+/// a body framed two ways cannot be mapped reliably.
+fn content_length(headers: &MultiMap<RangedHeader>) -> Result<Option<usize>, &'static str> {
+    let mut length = None;
+    for header in headers.get_ignore_ascii_case("content-length", Occurrence::All) {
+        let len = header
+            .value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| "Invalid Content-Length")?;
+        if length.is_some_and(|length| length != len) {
+            return Err("Conflicting Content-Length headers");
+        }
+        length = Some(len);
+    }
+    Ok(length)
+}
+
+fn decode_chunked(raw: &[u8], offset: usize) -> Result<(Vec<u8>, BodyMap), &'static str> {
+    let mut body = Vec::new();
+    let mut segments = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let line_end = find_crlf(raw, pos).ok_or("Missing chunk size line")?;
+        let size_line =
+            std::str::from_utf8(&raw[pos..line_end]).map_err(|_| "Invalid chunk size")?;
+        // Chunk extensions (";name=value") carry no body bytes.
+        let size_hex = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_hex, 16).map_err(|_| "Invalid chunk size")?;
+        pos = line_end + 2;
+
+        if size == 0 {
+            // Trailer fields, if any, are not part of the body.
+            return Ok((body, BodyMap { segments }));
+        }
+
+        // Compared against the bytes left, so a huge size cannot overflow.
+        let left = raw.len() - pos;
+        if size > left || left - size < 2 {
+            return Err("Truncated chunk");
+        }
+        let data_end = pos + size;
+        if &raw[data_end..data_end + 2] != b"\r\n" {
+            return Err("Truncated chunk");
+        }
+
+        segments.push(Segment {
+            body_start: body.len(),
+            transcript_start: offset + pos,
+            len: size,
+        });
+        body.extend_from_slice(&raw[pos..data_end]);
+        pos = data_end + 2;
+    }
+}
+
+fn find_crlf(raw: &[u8], from: usize) -> Option<usize> {
+    raw.get(from..)?
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|index| from + index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(fields: &[(&str, &str)]) -> MultiMap<RangedHeader> {
+        fields
+            .iter()
+            .map(|(name, value)| {
+                let header = RangedHeader {
+                    range: 0..0,
+                    value: value.to_string(),
+                };
+                (name.to_string(), header)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_chunks_with_extensions_and_trailers() {
+        let raw = b"4;name=value\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\n";
+        let (body, map) = decode_chunked(raw, 100).unwrap();
+
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(map.to_transcript(0..4), vec![114..118]);
+        assert_eq!(map.to_transcript(4..9), vec![123..128]);
+    }
+
+    #[test]
+    fn splits_ranges_crossing_a_chunk_boundary() {
+        let raw = b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let (_, map) = decode_chunked(raw, 0).unwrap();
+
+        let ranges = map.to_transcript(2..6);
+        assert_eq!(ranges, vec![5..7, 12..14]);
+        let revealed: Vec<u8> = ranges.into_iter().flat_map(|r| raw[r].to_vec()).collect();
+        assert_eq!(revealed, b"kipe");
+    }
+
+    #[test]
+    fn rejects_chunk_sizes_that_overflow() {
+        assert_eq!(
+            decode_chunked(b"ffffffffffffffec\r\nabc\r\n0\r\n\r\n", 0).unwrap_err(),
+            "Truncated chunk"
+        );
+        assert_eq!(
+            decode_chunked(b"ffffffffffffffffff\r\n", 0).unwrap_err(),
+            "Invalid chunk size"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        assert!(decode_chunked(b"5\r\nabc", 0).is_err());
+        assert!(decode_chunked(b"3\r\nabc", 0).is_err());
+        assert!(decode_chunked(b"3\r\nabcXY0\r\n\r\n", 0).is_err());
+    }
+
+    #[test]
+    fn honours_content_length() {
+        let (body, map) =
+            decode_body(b"{}trailing", 10, &headers(&[("Content-Length", "2")])).unwrap();
+        assert_eq!(body, b"{}");
+        assert_eq!(map.to_transcript(0..2), vec![10..12]);
+
+        let short = decode_body(b"{}", 0, &headers(&[("content-length", "5")]));
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn reads_to_the_end_without_framing_headers() {
+        let (body, _) = decode_body(b"{\"a\":1}", 0, &headers(&[])).unwrap();
+        assert_eq!(body, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn prefers_chunked_over_content_length() {
+        let fields = [
+            ("Transfer-Encoding", "gzip, Chunked"),
+            ("Content-Length", "1"),
+        ];
+        let (body, _) = decode_body(b"2\r\nok\r\n0\r\n\r\n", 0, &headers(&fields)).unwrap();
+        assert_eq!(body, b"ok");
+    }
+
+    #[test]
+    fn finds_chunked_in_any_transfer_encoding_field() {
+        let fields = [
+            ("Transfer-Encoding", "gzip"),
+            ("transfer-encoding", "chunked"),
+        ];
+        let (body, _) = decode_body(b"2\r\nok\r\n0\r\n\r\n", 0, &headers(&fields)).unwrap();
+        assert_eq!(body, b"ok");
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        let fields = [("Content-Length", "2"), ("content-length", "3")];
+        assert_eq!(
+            decode_body(b"{}x", 0, &headers(&fields)).unwrap_err(),
+            "Conflicting Content-Length headers"
+        );
+
+        let fields = [("Content-Length", "2"), ("CONTENT-LENGTH", " 2")];
+        let (body, _) = decode_body(b"{}x", 0, &headers(&fields)).unwrap();
+        assert_eq!(body, b"{}");
+    }
+}
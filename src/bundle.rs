@@ -0,0 +1,177 @@
+use std::{
+    io::ErrorKind,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::errors::Result;
+
+/// Version of the session bundle format. Bump it on any breaking change to
+/// the serialized layout.
+///
+/// 2: `TranscriptRecord::redacted` holds base64 bytes instead of lossy UTF-8.
+pub const BUNDLE_VERSION: u32 = 2;
+
+/// Byte written in place of every redacted byte of a stored transcript.
+pub const REDACTION_PLACEHOLDER: u8 = b'X';
+
+/// Everything kept about a finished proving session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionBundle {
+    pub version: u32,
+    pub server_name: String,
+    /// Unix time in milliseconds.
+    pub started_at: u64,
+    /// Unix time in milliseconds.
+    pub finished_at: u64,
+    pub limits: ProtocolLimits,
+    pub redaction_placeholder: char,
+    pub sent: TranscriptRecord,
+    pub received: TranscriptRecord,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProtocolLimits {
+    pub max_sent_data: usize,
+    pub max_recv_data: usize,
+}
+
+/// One direction of the transcript as the verifier saw it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TranscriptRecord {
+    pub len: usize,
+    pub revealed: Vec<Range<usize>>,
+    /// The transcript with every byte outside `revealed` replaced by the
+    /// placeholder, base64-encoded. Kept as bytes, since a revealed range may
+    /// end inside a multi-byte character.
+    #[serde(with = "base64_bytes")]
+    pub redacted: Vec<u8>,
+}
+
+impl TranscriptRecord {
+    pub fn new(transcript: &[u8], revealed: &[Range<usize>]) -> Self {
+        let mut redacted = vec![REDACTION_PLACEHOLDER; transcript.len()];
+        for range in revealed {
+            let range = range.start.min(transcript.len())..range.end.min(transcript.len());
+            redacted[range.clone()].copy_from_slice(&transcript[range]);
+        }
+
+        Self {
+            len: transcript.len(),
+            revealed: revealed.to_vec(),
+            redacted,
+        }
+    }
+}
+
+impl SessionBundle {
+    /// Writes the bundle as pretty JSON into `dir` and returns the file path.
+    /// Never overwrites an existing bundle: sessions against the same server
+    /// started in the same millisecond get a numbered suffix.
+    pub async fn write_to(&self, dir: &Path) -> Result<PathBuf> {
+        tokio::fs::create_dir_all(dir).await?;
+        let bytes = serde_json::to_vec_pretty(self)?;
+
+        let stem = format!("{}-{}", self.server_name, self.started_at);
+        let mut attempt = 0;
+        loop {
+            let path = match attempt {
+                0 => dir.join(format!("{}.json", stem)),
+                n => dir.join(format!("{}-{}.json", stem, n)),
+            };
+            // `create_new` claims the name atomically, so concurrent writers
+            // cannot both pick it.
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(mut file) => {
+                    file.write_all(&bytes).await?;
+                    file.flush().await?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+}
+
+/// Current Unix time in milliseconds.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn never_overwrites_a_bundle() {
+        let dir = std::env::temp_dir().join(format!("krithon-bundle-test-{}", std::process::id()));
+        let bundle = SessionBundle {
+            version: BUNDLE_VERSION,
+            server_name: "api.example.com".to_string(),
+            started_at: 1,
+            finished_at: 2,
+            limits: ProtocolLimits {
+                max_sent_data: 10,
+                max_recv_data: 10,
+            },
+            redaction_placeholder: REDACTION_PLACEHOLDER as char,
+            sent: TranscriptRecord::new(b"GET /", &[0..3, 4..5]),
+            received: TranscriptRecord::new(b"secret", &[]),
+        };
+
+        let (first, second) = tokio::join!(bundle.write_to(&dir), bundle.write_to(&dir));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_ne!(first, second);
+        let mut names = [first, second].map(|path| path.file_name().unwrap().to_owned());
+        names.sort();
+        assert_eq!(
+            names,
+            ["api.example.com-1-1.json", "api.example.com-1.json"]
+        );
+    }
+
+    #[test]
+    fn keeps_split_characters_byte_exact() {
+        // A range ending inside "é" reveals only its first byte.
+        let transcript = "né!".as_bytes();
+        let record = TranscriptRecord::new(transcript, &[0..2, 3..4]);
+        assert_eq!(record.redacted, [b'n', 0xC3, REDACTION_PLACEHOLDER, b'!']);
+        assert_eq!(record.redacted.len(), record.len);
+
+        let json = serde_json::to_string(&record).unwrap();
+        let stored: TranscriptRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(stored.redacted, record.redacted);
+        for range in &stored.revealed {
+            assert_eq!(stored.redacted[range.clone()], transcript[range.clone()]);
+        }
+    }
+}
@@ -1,82 +1,254 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, io, time::Instant};
 
-use serde_json::Value;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, Stdin, Stdout};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Stdin, Stdout},
+    sync::mpsc,
+    task::JoinHandle,
+};
 
-use crate::errors::{Errors, Result};
+use crate::errors::{Errors, Result, Stage};
+use crate::messages::{
+    Chunk, ContinuationFrame, ContinuationType, Frame, FrameBody, LogMessage, Notice, Outcome,
+    Progress, ProgressReport,
+};
 
-// use crate::errors::{Errors, Result};
+/// Version of the message schema spoken with the extension. Bump it on any
+/// incompatible change to requests or frames.
+pub const PROTOCOL_VERSION: u32 = 1;
 
-#[derive(serde::Serialize)]
-pub enum MessageType {
-    Message,
-    Logging,
+/// Oldest protocol version this host still serves.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Largest message accepted from the extension. Proof requests are small, so
+/// this stays well below the 64 MiB Chrome allows towards the host.
+pub const MAX_INBOUND_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Largest frame Chrome accepts from a native host.
+pub const MAX_OUTBOUND_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Longest request id accepted, in bytes. Every frame echoes the id, so it
+/// has to fit the envelope of a continuation frame.
+pub const MAX_REQUEST_ID_LENGTH: usize = 256;
+
+/// Bytes of the serialized message carried by one continuation frame. Escaping
+/// the slice as a JSON string at most doubles it, the rest is left for the
+/// envelope.
+const CONTINUATION_CHUNK_SIZE: usize = MAX_OUTBOUND_FRAME_SIZE / 2 - 4096;
+
+/// Encoded frames waiting for the writer task.
+type FrameQueue = mpsc::UnboundedSender<Vec<Vec<u8>>>;
+
+/// Where messages produced while proving end up.
+#[derive(Clone)]
+enum Sink {
+    /// Length-prefixed JSON frames for the Chrome extension, queued for the
+    /// writer task shared by all sessions. A run of continuation frames is
+    /// queued as one item so nothing interleaves with it.
+    NativeHost(FrameQueue),
+    /// Human-readable lines for the `prove` subcommand.
+    Console,
 }
 
-// Helper function to send error messages as JSON
-pub async fn send_error_response(error_msg: &str, stdout: &mut Stdout) {
-    let json_error: Value = serde_json::json!({
-        "type": "Error",
-        "message": error_msg
-    });
-    let response_bytes = match serde_json::to_vec(&json_error) {
-        Ok(bytes) => bytes,
-        // TODO: how to handle this error?
-        // If we cant send the error message, we should return an error
-        Err(_err) => return,
-    };
-    let response_len = response_bytes.len() as u32;
+/// Destination of the messages of one request, together with the request id
+/// every frame echoes and the pipeline stage errors are reported at.
+#[derive(Clone)]
+pub struct Output {
+    sink: Sink,
+    id: Option<String>,
+    stage: Stage,
+    /// When the request was received; progress events report time since then.
+    started: Instant,
+}
 
-    // Send the response length and error message
-    if stdout.write_all(&response_len.to_le_bytes()).await.is_err() {
-        // TODO: CONSIDER: what to do if we can't send the error message?
+impl Output {
+    /// Returns the native-messaging output and the task writing its frames to
+    /// stdout. The task finishes once every clone of the output is dropped and
+    /// all queued frames are written, so a session aborted mid-message never
+    /// leaves a partial frame behind.
+    pub fn native_host() -> (Self, JoinHandle<()>) {
+        let (frames, mut queue) = mpsc::unbounded_channel::<Vec<Vec<u8>>>();
+        let writer = tokio::spawn(async move {
+            let mut stdout = tokio::io::stdout();
+            while let Some(frames) = queue.recv().await {
+                if write_frames(&mut stdout, &frames).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let output = Self {
+            sink: Sink::NativeHost(frames),
+            id: None,
+            stage: Stage::default(),
+            started: Instant::now(),
+        };
+        (output, writer)
     }
-    if stdout.write_all(&response_bytes).await.is_err() {
-        // TODO: CONSIDER: what to do if we can't send the error message?
+
+    pub fn console() -> Self {
+        Self {
+            sink: Sink::Console,
+            id: None,
+            stage: Stage::default(),
+            started: Instant::now(),
+        }
     }
-    if stdout.flush().await.is_err() {
-        // TODO: CONSIDER: what to do if we can't send the error message?
-    };
-}
 
-// Helper function to send JSON responses
-pub async fn send_response(json: Value, message_type: MessageType, stdout: &mut Stdout) {
-    let json_message = serde_json::json!({
-        "type": message_type,
-        "message": json
-    });
+    /// Returns an output sharing the same writer whose frames echo `request_id`.
+    pub fn with_id(&self, request_id: Option<String>) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            id: request_id,
+            stage: Stage::default(),
+            started: Instant::now(),
+        }
+    }
 
-    let response_bytes = match serde_json::to_vec(&json_message) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            send_error_response(&e.to_string(), stdout).await;
+    /// Records the stage the request has reached; errors sent afterwards report it.
+    pub fn set_stage(&mut self, stage: Stage) {
+        self.stage = stage;
+    }
+}
+
+/// Sends `body` as a frame of the request `output` belongs to.
+async fn send(body: FrameBody, output: &mut Output) {
+    let frames = match &output.sink {
+        Sink::NativeHost(frames) => frames,
+        Sink::Console => {
+            print_to_console(&body);
             return;
         }
     };
-    let response_len = response_bytes.len() as u32;
 
-    if let Err(e) = stdout.write_all(&response_len.to_le_bytes()).await {
-        send_error_response(&e.to_string(), stdout).await;
-        return;
+    let frame = Frame {
+        id: output.id.clone(),
+        body,
+    };
+    if let Err(e) = queue_frame(frames, &frame) {
+        // TODO: CONSIDER: what to do if we can't send the error message?
+        if !matches!(frame.body, FrameBody::Error(_)) {
+            let error = Frame {
+                id: frame.id,
+                body: FrameBody::Error(e.report(output.stage)),
+            };
+            let _ = queue_frame(frames, &error);
+        }
     }
-    if let Err(e) = stdout.write_all(&response_bytes).await {
-        send_error_response(&e.to_string(), stdout).await;
-        return;
+}
+
+fn print_to_console(body: &FrameBody) {
+    match body {
+        FrameBody::Message(notice) | FrameBody::Cancelled(notice) => println!("{}", notice.message),
+        FrameBody::Logging(log) => eprintln!("{}", log.logging),
+        FrameBody::Error(report) => {
+            eprintln!(
+                "Error [{}] at {}: {}",
+                report.code, report.stage, report.message
+            )
+        }
+        // Already printed with the `Error` frame sent before it.
+        FrameBody::Failed(_) => {}
+        FrameBody::Done(outcome) => match serde_json::to_string(outcome) {
+            Ok(outcome) => println!("{}", outcome),
+            Err(e) => eprintln!("Failed to serialize outcome: {}", e),
+        },
+        FrameBody::Progress(progress) => eprintln!(
+            "[{}/{}] {} ({} ms)",
+            progress.step, progress.total_steps, progress.event, progress.elapsed_ms
+        ),
     }
-    if let Err(e) = stdout.flush().await {
-        send_error_response(&e.to_string(), stdout).await;
-        return;
+}
+
+/// Queues `frame`, or, when it would exceed `MAX_OUTBOUND_FRAME_SIZE`, its
+/// `message` split over consecutive `ContinuationFrame`s.
+fn queue_frame(frames: &FrameQueue, frame: &Frame) -> Result<()> {
+    let bytes = serde_json::to_vec(frame)?;
+    if bytes.len() <= MAX_OUTBOUND_FRAME_SIZE {
+        return queue_frames(frames, vec![bytes]);
     }
+
+    let mut body = serde_json::to_value(&frame.body)?;
+    let message = serde_json::to_string(&body["message"].take())?;
+    let chunks = split_chunks(&message, CONTINUATION_CHUNK_SIZE);
+    let continuation_frames = chunks
+        .iter()
+        .enumerate()
+        .map(|(index, data)| {
+            serde_json::to_vec(&ContinuationFrame {
+                frame_type: ContinuationType::Continuation,
+                original_type: frame.body.message_type(),
+                id: frame.id.as_deref(),
+                chunk: Chunk {
+                    index,
+                    count: chunks.len(),
+                },
+                data,
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    queue_frames(frames, continuation_frames)
 }
 
-pub async fn read_message(stdin: &mut Stdin) -> Result<String> {
+fn queue_frames(frames: &FrameQueue, run: Vec<Vec<u8>>) -> Result<()> {
+    // The writer task only stops once stdout is gone.
+    frames
+        .send(run)
+        .map_err(|_| Errors::IoError(io::ErrorKind::BrokenPipe.into()))
+}
+
+/// Splits `text` into slices of at most `max_len` bytes without cutting a character.
+fn split_chunks(text: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = max_len.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// Writes native-messaging frames: each a little-endian length followed by the payload.
+async fn write_frames(stdout: &mut Stdout, frames: &[Vec<u8>]) -> io::Result<()> {
+    for bytes in frames {
+        let len = bytes.len() as u32;
+        stdout.write_all(&len.to_le_bytes()).await?;
+        stdout.write_all(bytes).await?;
+    }
+    stdout.flush().await
+}
+
+/// Reads the next message from the extension, or `None` once it closed stdin.
+pub async fn read_message(stdin: &mut Stdin) -> Result<Option<String>> {
     // Read the length of the message (4 bytes, little-endian)
     let mut len_bytes = [0u8; 4];
-    if stdin.read_exact(&mut len_bytes).await.is_err() {
-        return Err(Errors::FailedToReadSizeFromExtension);
+    match stdin.read_exact(&mut len_bytes).await {
+        Ok(_) => {}
+        // Chrome closes the pipe when the extension disconnects.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(_) => return Err(Errors::FailedToReadSizeFromExtension),
     }
     let len = u32::from_le_bytes(len_bytes) as usize;
 
+    if len > MAX_INBOUND_MESSAGE_SIZE {
+        // Skip the payload without buffering it, so the next frame is read
+        // from the right position.
+        let mut payload = AsyncReadExt::take(&mut *stdin, len as u64);
+        match tokio::io::copy(&mut payload, &mut tokio::io::sink()).await {
+            Ok(skipped) if skipped == len as u64 => {}
+            _ => return Err(Errors::FailedToReadMessageFromExtension),
+        }
+        return Err(Errors::MessageTooLarge {
+            size: len,
+            limit: MAX_INBOUND_MESSAGE_SIZE,
+        });
+    }
+
     // Read the message
     let mut buffer = vec![0u8; len];
     if stdin.read_exact(&mut buffer).await.is_err() {
@@ -85,12 +257,111 @@ pub async fn read_message(stdin: &mut Stdin) -> Result<String> {
     let message: Cow<'_, str> = String::from_utf8_lossy(&buffer);
     let message_string = message.to_string();
 
-    Ok(message_string)
+    Ok(Some(message_string))
+}
+
+/// Sends an `Error` frame describing `error`.
+pub async fn send_error(error: &Errors, output: &mut Output) {
+    let report = error.report(output.stage);
+    send(FrameBody::Error(report), output).await;
+}
+
+/// Sends a plain notice about the request, e.g. that it was received.
+pub async fn send_message(message: &str, output: &mut Output) {
+    let notice = Notice {
+        message: message.to_string(),
+    };
+    send(FrameBody::Message(notice), output).await;
+}
+
+pub async fn logging_message(output: &mut Output, message: &str) {
+    let log = LogMessage {
+        logging: message.to_string(),
+    };
+    send(FrameBody::Logging(log), output).await;
+}
+
+/// Reports that the session reached `progress`.
+pub async fn send_progress(progress: Progress, output: &mut Output) {
+    let report = ProgressReport {
+        event: progress,
+        step: progress.step(),
+        total_steps: Progress::TOTAL_STEPS,
+        elapsed_ms: output.started.elapsed().as_millis() as u64,
+    };
+    send(FrameBody::Progress(report), output).await;
+}
+
+/// Sends the terminal frame of a successful request.
+pub async fn send_done(outcome: Outcome, output: &mut Output) {
+    send(FrameBody::Done(outcome), output).await;
+}
+
+/// Reports the error and sends the terminal frame of a failed request, which
+/// carries the same report as the `Error` frame.
+pub async fn send_failed(error: &Errors, output: &mut Output) {
+    send_error(error, output).await;
+    let report = error.report(output.stage);
+    send(FrameBody::Failed(report), output).await;
+}
+
+/// Sends the terminal frame of a cancelled request.
+pub async fn send_cancelled(output: &mut Output) {
+    let notice = Notice {
+        message: "Cancelled".to_string(),
+    };
+    send(FrameBody::Cancelled(notice), output).await;
 }
 
-pub async fn logging_message(stdout: &mut Stdout, message: &str) {
-    let logging_message = serde_json::json!({
-        "logging": message,
-    });
-    send_response(logging_message, MessageType::Logging, stdout).await;
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn splits_oversized_frames_into_continuations() {
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        let logging = "é".repeat(MAX_OUTBOUND_FRAME_SIZE);
+        let frame = Frame {
+            id: Some("x".repeat(MAX_REQUEST_ID_LENGTH)),
+            body: FrameBody::Logging(LogMessage {
+                logging: logging.clone(),
+            }),
+        };
+        queue_frame(&queue, &frame).unwrap();
+
+        let run = queued.try_recv().unwrap();
+        assert!(run.len() > 1);
+        let mut data = String::new();
+        for (index, bytes) in run.iter().enumerate() {
+            assert!(bytes.len() <= MAX_OUTBOUND_FRAME_SIZE);
+            let frame: Value = serde_json::from_slice(bytes).unwrap();
+            assert_eq!(frame["type"], "Continuation");
+            assert_eq!(frame["original_type"], "Logging");
+            assert_eq!(frame["chunk"]["index"], index);
+            assert_eq!(frame["chunk"]["count"], run.len());
+            data.push_str(frame["data"].as_str().unwrap());
+        }
+        let message: Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(message["logging"], logging);
+    }
+
+    #[test]
+    fn queues_small_frames_whole() {
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        let frame = Frame {
+            id: None,
+            body: FrameBody::Logging(LogMessage {
+                logging: "hi".to_string(),
+            }),
+        };
+        queue_frame(&queue, &frame).unwrap();
+
+        let run = queued.try_recv().unwrap();
+        let frame: Value = serde_json::from_slice(&run[0]).unwrap();
+        assert_eq!(run.len(), 1);
+        assert_eq!(frame["type"], "Logging");
+        assert_eq!(frame["message"]["logging"], "hi");
+    }
 }
@@ -1,10 +1,17 @@
 use crate::request::Rule as RequestRule;
 use crate::response::Rule as ResponseRule;
-use hyper::Error as HyperError;
+use crate::session::SessionState;
+use hyper::{http::Error as HttpError, Error as HyperError};
 use pest::error::Error as PestError;
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::io;
 use thiserror::Error;
 use tlsn_common::config::ProtocolConfigBuilderError;
+use tlsn_core::{
+    presentation::PresentationBuilderError,
+    transcript::{TranscriptCommitConfigBuilderError, TranscriptProofBuilderError},
+};
 use tlsn_prover::{ProverConfigBuilderError, ProverError};
 use tokio::task::JoinError;
 
@@ -14,10 +21,33 @@ pub type Result<T> = std::result::Result<T, Errors>;
 pub enum Errors {
     #[error("Failed to read size from extension")]
     FailedToReadSizeFromExtension,
-    
+
     #[error("Failed to read message from extension")]
     FailedToReadMessageFromExtension,
 
+    #[error("Message of {size} bytes exceeds the limit of {limit} bytes")]
+    MessageTooLarge { size: usize, limit: usize },
+
+    #[error("Failed to parse message: {0}")]
+    InvalidMessage(serde_json::Error),
+
+    #[error(
+        "Protocol version {requested} is not supported, supported versions are {min} to {max}"
+    )]
+    UnsupportedProtocolVersion { requested: u32, min: u32, max: u32 },
+
+    #[error("Request id of {length} bytes exceeds the limit of {limit} bytes")]
+    RequestIdTooLong { length: usize, limit: usize },
+
+    #[error("Request id '{id}' is already {state}")]
+    DuplicateRequestId { id: String, state: SessionState },
+
+    #[error("No session with request id '{0}'")]
+    UnknownRequestId(String),
+
+    #[error("Proving session ended unexpectedly")]
+    SessionPanicked,
+
     #[error("Invalid scheme")]
     InvalidScheme,
 
@@ -27,6 +57,60 @@ pub enum Errors {
     #[error("Request URI does not have a port")]
     MissingPort,
 
+    #[error("Unsupported HTTP method '{0}'")]
+    UnsupportedMethod(String),
+
+    #[error("Invalid request body: {0}")]
+    InvalidBody(String),
+
+    #[error("{0}")]
+    InvalidHeader(String),
+
+    #[error("Failed to connect to verifier: {0}")]
+    VerifierConnectionError(io::Error),
+
+    #[error("Failed to connect to server: {0}")]
+    ServerConnectionError(io::Error),
+
+    #[error("TLS handshake with the server failed while {step}: {message}")]
+    TlsHandshakeFailed {
+        step: HandshakeStep,
+        message: String,
+    },
+
+    #[error("Server responded with status {status}, accepted statuses are {accepted:?}")]
+    UnexpectedStatus { status: u16, accepted: Vec<u16> },
+
+    #[error("Invalid status code {0}")]
+    InvalidStatusCode(u16),
+
+    #[error("Invalid redaction policy: {0}")]
+    InvalidPolicy(String),
+
+    #[error("Redaction policy matched nothing in the {direction} data")]
+    PolicyMatchedNothing { direction: Direction },
+
+    #[error("The {direction} data needs at least {required} bytes, the limit is {limit}")]
+    LimitsExceeded {
+        direction: Direction,
+        limit: usize,
+        required: usize,
+    },
+
+    #[error("Failed to parse {direction} transcript: {message}")]
+    TranscriptParseError {
+        direction: Direction,
+        message: String,
+    },
+
+    #[error("Range {start}..{end} lies outside the {len} bytes of {direction} data")]
+    RangeOutOfBounds {
+        direction: Direction,
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+
     #[error(transparent)]
     Utf8ConversionError(#[from] std::string::FromUtf8Error),
 
@@ -39,23 +123,355 @@ pub enum Errors {
     #[error(transparent)]
     ProtocolConfigBuilderError(#[from] ProtocolConfigBuilderError),
 
+    #[error(transparent)]
+    TranscriptCommitConfigBuilderError(#[from] TranscriptCommitConfigBuilderError),
+
+    #[error(transparent)]
+    TranscriptProofBuilderError(#[from] TranscriptProofBuilderError),
+
+    #[error(transparent)]
+    PresentationBuilderError(#[from] PresentationBuilderError),
+
+    #[error(transparent)]
+    BincodeError(#[from] bincode::Error),
+
     #[error(transparent)]
     IoError(#[from] io::Error),
 
     #[error(transparent)]
     HyperError(#[from] HyperError),
 
+    #[error(transparent)]
+    HttpError(#[from] HttpError),
+
     #[error(transparent)]
     JoinError(#[from] JoinError),
 
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
     #[error(transparent)]
     PestRequestError(#[from] PestError<RequestRule>),
 
     #[error(transparent)]
     PestResponseError(#[from] PestError<ResponseRule>),
+}
 
-    #[error("{0}")]
-    StringError(String),
+/// Direction of the transcript an error refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Sent => write!(f, "sent"),
+            Direction::Received => write!(f, "received"),
+        }
+    }
+}
+
+/// Where the MPC-TLS connection to the server broke down before the first
+/// response arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeStep {
+    /// Handing the TCP connection to the prover.
+    Connect,
+    /// Setting up HTTP on top of the TLS connection.
+    HttpHandshake,
+    /// Sending the request, the first data that goes through TLS.
+    Request,
+}
+
+impl std::fmt::Display for HandshakeStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeStep::Connect => write!(f, "connecting"),
+            HandshakeStep::HttpHandshake => write!(f, "starting HTTP"),
+            HandshakeStep::Request => write!(f, "sending the request"),
+        }
+    }
+}
+
+/// Coarse failure kind the extension can branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Extension,
+    Session,
+    Request,
+    Policy,
+    Verifier,
+    Server,
+    Transcript,
+    Limits,
+    Protocol,
+    Notarization,
+    Internal,
+}
+
+/// Step of the proving pipeline a session is in.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Reading and parsing the message from the extension.
+    #[default]
+    Message,
+    Validation,
+    Request,
+    VerifierConnection,
+    Setup,
+    ServerConnection,
+    Exchange,
+    Redaction,
+    Proof,
+    Finalization,
+    Persistence,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stage = match self {
+            Stage::Message => "message",
+            Stage::Validation => "validation",
+            Stage::Request => "request",
+            Stage::VerifierConnection => "verifier_connection",
+            Stage::Setup => "setup",
+            Stage::ServerConnection => "server_connection",
+            Stage::Exchange => "exchange",
+            Stage::Redaction => "redaction",
+            Stage::Proof => "proof",
+            Stage::Finalization => "finalization",
+            Stage::Persistence => "persistence",
+        };
+        write!(f, "{}", stage)
+    }
+}
+
+/// Body of an `Error` frame.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorReport {
+    pub code: &'static str,
+    pub category: ErrorCategory,
+    pub message: String,
+    pub details: Option<ErrorDetails>,
+    pub stage: Stage,
+}
+
+/// `details` of an `Error` frame. Which shape appears is determined by the
+/// error `code` noted on each variant.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ErrorDetails {
+    /// `duplicate_request_id`
+    DuplicateRequestId { id: String, state: SessionState },
+    /// `message_too_large`
+    MessageSize { size: usize, limit: usize },
+    /// `request_id_too_long`
+    RequestIdLength { length: usize, limit: usize },
+    /// `unsupported_protocol_version`
+    ProtocolVersion { requested: u32, min: u32, max: u32 },
+    /// `unknown_request_id`
+    RequestId { id: String },
+    /// `unsupported_method`
+    Method { method: String },
+    /// `unexpected_status`
+    UnexpectedStatus { status: u16, accepted: Vec<u16> },
+    /// `invalid_status_code`
+    Status { status: u16 },
+    /// `policy_matched_nothing`, `transcript_parse_failed`,
+    /// `request_transcript_parse_failed` and `response_transcript_parse_failed`
+    Direction { direction: Direction },
+    /// `limits_exceeded`
+    LimitsExceeded {
+        direction: Direction,
+        limit: usize,
+        required: usize,
+    },
+    /// `range_out_of_bounds`
+    RangeOutOfBounds {
+        direction: Direction,
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    /// `tls_handshake_failed`
+    TlsHandshake { step: HandshakeStep },
+}
+
+impl Errors {
+    /// Stable identifier of the error. Existing codes must never change
+    /// meaning, the extension matches on them.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Errors::FailedToReadSizeFromExtension => "extension_read_size_failed",
+            Errors::FailedToReadMessageFromExtension => "extension_read_message_failed",
+            Errors::MessageTooLarge { .. } => "message_too_large",
+            Errors::InvalidMessage(_) => "invalid_message",
+            Errors::UnsupportedProtocolVersion { .. } => "unsupported_protocol_version",
+            Errors::RequestIdTooLong { .. } => "request_id_too_long",
+            Errors::DuplicateRequestId { .. } => "duplicate_request_id",
+            Errors::UnknownRequestId(_) => "unknown_request_id",
+            Errors::SessionPanicked => "session_panicked",
+            Errors::InvalidScheme => "invalid_scheme",
+            Errors::MissingAuthority => "missing_authority",
+            Errors::MissingPort => "missing_port",
+            Errors::UnsupportedMethod(_) => "unsupported_method",
+            Errors::InvalidBody(_) => "invalid_body",
+            Errors::InvalidHeader(_) => "invalid_header",
+            Errors::VerifierConnectionError(_) => "verifier_unreachable",
+            Errors::ServerConnectionError(_) => "server_unreachable",
+            Errors::TlsHandshakeFailed { .. } => "tls_handshake_failed",
+            Errors::UnexpectedStatus { .. } => "unexpected_status",
+            Errors::InvalidStatusCode(_) => "invalid_status_code",
+            Errors::InvalidPolicy(_) => "invalid_policy",
+            Errors::PolicyMatchedNothing { .. } => "policy_matched_nothing",
+            Errors::LimitsExceeded { .. } => "limits_exceeded",
+            Errors::TranscriptParseError { .. } => "transcript_parse_failed",
+            Errors::RangeOutOfBounds { .. } => "range_out_of_bounds",
+            Errors::Utf8ConversionError(_) => "transcript_not_utf8",
+            Errors::ProverConfigBuilderError(_) => "prover_config_invalid",
+            Errors::ProverError(_) => "prover_failed",
+            Errors::ProtocolConfigBuilderError(_) => "protocol_config_invalid",
+            Errors::TranscriptCommitConfigBuilderError(_) => "commit_config_invalid",
+            Errors::TranscriptProofBuilderError(_) => "transcript_proof_failed",
+            Errors::PresentationBuilderError(_) => "presentation_failed",
+            Errors::BincodeError(_) => "serialization_failed",
+            Errors::IoError(_) => "io_error",
+            Errors::HyperError(_) => "http_exchange_failed",
+            Errors::HttpError(_) => "request_build_failed",
+            Errors::JoinError(_) => "task_failed",
+            Errors::JsonError(_) => "json_error",
+            Errors::PestRequestError(_) => "request_transcript_parse_failed",
+            Errors::PestResponseError(_) => "response_transcript_parse_failed",
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Errors::FailedToReadSizeFromExtension
+            | Errors::FailedToReadMessageFromExtension
+            | Errors::MessageTooLarge { .. }
+            | Errors::InvalidMessage(_)
+            | Errors::UnsupportedProtocolVersion { .. }
+            | Errors::RequestIdTooLong { .. } => ErrorCategory::Extension,
+            Errors::DuplicateRequestId { .. } | Errors::UnknownRequestId(_) => {
+                ErrorCategory::Session
+            }
+            Errors::InvalidScheme
+            | Errors::MissingAuthority
+            | Errors::MissingPort
+            | Errors::UnsupportedMethod(_)
+            | Errors::InvalidBody(_)
+            | Errors::InvalidHeader(_)
+            | Errors::InvalidStatusCode(_)
+            | Errors::HttpError(_) => ErrorCategory::Request,
+            Errors::InvalidPolicy(_) | Errors::PolicyMatchedNothing { .. } => ErrorCategory::Policy,
+            Errors::VerifierConnectionError(_) => ErrorCategory::Verifier,
+            Errors::ServerConnectionError(_)
+            | Errors::TlsHandshakeFailed { .. }
+            | Errors::UnexpectedStatus { .. }
+            | Errors::HyperError(_) => ErrorCategory::Server,
+            Errors::TranscriptParseError { .. }
+            | Errors::Utf8ConversionError(_)
+            | Errors::PestRequestError(_)
+            | Errors::PestResponseError(_) => ErrorCategory::Transcript,
+            Errors::LimitsExceeded { .. } => ErrorCategory::Limits,
+            Errors::ProverConfigBuilderError(_)
+            | Errors::ProverError(_)
+            | Errors::ProtocolConfigBuilderError(_) => ErrorCategory::Protocol,
+            Errors::TranscriptCommitConfigBuilderError(_)
+            | Errors::TranscriptProofBuilderError(_)
+            | Errors::PresentationBuilderError(_) => ErrorCategory::Notarization,
+            Errors::SessionPanicked
+            | Errors::RangeOutOfBounds { .. }
+            | Errors::BincodeError(_)
+            | Errors::IoError(_)
+            | Errors::JoinError(_)
+            | Errors::JsonError(_) => ErrorCategory::Internal,
+        }
+    }
 
+    /// Machine-readable specifics of the error, `null` when there are none.
+    pub fn details(&self) -> Option<ErrorDetails> {
+        let details = match self {
+            Errors::DuplicateRequestId { id, state } => ErrorDetails::DuplicateRequestId {
+                id: id.clone(),
+                state: *state,
+            },
+            Errors::MessageTooLarge { size, limit } => ErrorDetails::MessageSize {
+                size: *size,
+                limit: *limit,
+            },
+            Errors::RequestIdTooLong { length, limit } => ErrorDetails::RequestIdLength {
+                length: *length,
+                limit: *limit,
+            },
+            Errors::UnsupportedProtocolVersion {
+                requested,
+                min,
+                max,
+            } => ErrorDetails::ProtocolVersion {
+                requested: *requested,
+                min: *min,
+                max: *max,
+            },
+            Errors::UnknownRequestId(id) => ErrorDetails::RequestId { id: id.clone() },
+            Errors::UnsupportedMethod(method) => ErrorDetails::Method {
+                method: method.clone(),
+            },
+            Errors::UnexpectedStatus { status, accepted } => ErrorDetails::UnexpectedStatus {
+                status: *status,
+                accepted: accepted.clone(),
+            },
+            Errors::InvalidStatusCode(status) => ErrorDetails::Status { status: *status },
+            Errors::PolicyMatchedNothing { direction }
+            | Errors::TranscriptParseError { direction, .. } => ErrorDetails::Direction {
+                direction: *direction,
+            },
+            Errors::LimitsExceeded {
+                direction,
+                limit,
+                required,
+            } => ErrorDetails::LimitsExceeded {
+                direction: *direction,
+                limit: *limit,
+                required: *required,
+            },
+            Errors::RangeOutOfBounds {
+                direction,
+                start,
+                end,
+                len,
+            } => ErrorDetails::RangeOutOfBounds {
+                direction: *direction,
+                start: *start,
+                end: *end,
+                len: *len,
+            },
+            Errors::TlsHandshakeFailed { step, .. } => ErrorDetails::TlsHandshake { step: *step },
+            Errors::PestRequestError(_) => ErrorDetails::Direction {
+                direction: Direction::Sent,
+            },
+            Errors::PestResponseError(_) => ErrorDetails::Direction {
+                direction: Direction::Received,
+            },
+            _ => return None,
+        };
+        Some(details)
+    }
 
-} 
\ No newline at end of file
+    pub fn report(&self, stage: Stage) -> ErrorReport {
+        ErrorReport {
+            code: self.code(),
+            category: self.category(),
+            message: self.to_string(),
+            details: self.details(),
+            stage,
+        }
+    }
+}
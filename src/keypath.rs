@@ -0,0 +1,435 @@
+use std::{ops::Range, str::FromStr};
+
+use crate::ast::{RangedMember, RangedValue};
+use crate::policy::Occurrence;
+
+/// A path into a JSON body, e.g. `transactions[0].amount`.
+///
+/// - `name` or `["name"]` selects an object member, compared with the key as
+///   decoded, so `café` also matches `"caf\u00e9"`. Arrays on the way are
+///   searched element by element, so `transactions.id` is the same as
+///   `transactions[*].id`.
+/// - `[n]` selects the n-th array element.
+/// - `*` or `[*]` selects every member of an object or element of an array.
+/// - `..` selects what follows at any depth, e.g. `..id` or `data..[0]`.
+///
+/// A `pair:`, `value:`, `key:` or `object:` prefix picks what is disclosed of
+/// each match, see `Disclosure`. Without a prefix the key and value are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keypath {
+    segments: Vec<Segment>,
+    disclosure: Disclosure,
+}
+
+/// What part of a selected object member is revealed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Disclosure {
+    /// The quoted key, the colon and the value.
+    #[default]
+    Pair,
+    /// Only the value, so a verifier does not learn which key held it.
+    Value,
+    /// Only the quoted key, proving the member exists while hiding its value.
+    /// Array elements have no key and reveal nothing.
+    Key,
+    /// The whole object or array directly enclosing the match.
+    Object,
+}
+
+impl Disclosure {
+    const PREFIXES: [(&'static str, Disclosure); 4] = [
+        ("pair:", Disclosure::Pair),
+        ("value:", Disclosure::Value),
+        ("key:", Disclosure::Key),
+        ("object:", Disclosure::Object),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+    /// Recursive descent: the rest of the path may start at any depth.
+    Descendants,
+}
+
+/// A value selected by a keypath, with the range of the object key it is
+/// stored under and the object or array holding it. Array elements have no
+/// key and the root has no parent.
+#[derive(Debug, Clone, Copy)]
+pub struct Selected<'a> {
+    pub key_range: Option<&'a Range<usize>>,
+    pub value: &'a RangedValue,
+    pub parent: Option<&'a RangedValue>,
+}
+
+impl Selected<'_> {
+    /// Range to reveal for the match under `disclosure`, if any.
+    pub fn disclosed_range(&self, disclosure: Disclosure) -> Option<Range<usize>> {
+        let value = self.value.get_range();
+        match disclosure {
+            // Spanning from the key keeps whatever whitespace surrounds the colon.
+            Disclosure::Pair => Some(match self.key_range {
+                Some(key) => key.start..value.end,
+                None => value,
+            }),
+            Disclosure::Value => Some(value),
+            Disclosure::Key => self.key_range.cloned(),
+            Disclosure::Object => Some(self.parent.map_or(value, RangedValue::get_range)),
+        }
+    }
+}
+
+impl FromStr for Keypath {
+    type Err = String;
+
+    fn from_str(keypath: &str) -> Result<Self, Self::Err> {
+        let (disclosure, keypath) = Disclosure::PREFIXES
+            .iter()
+            .find_map(|(prefix, disclosure)| {
+                keypath.strip_prefix(prefix).map(|rest| (*disclosure, rest))
+            })
+            .unwrap_or((Disclosure::default(), keypath));
+
+        let mut segments = Vec::new();
+        let mut rest = keypath;
+        let mut first = true;
+
+        while first || !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix("..") {
+                segments.push(Segment::Descendants);
+                rest = if tail.starts_with('[') {
+                    tail
+                } else {
+                    parse_member(tail, &mut segments)?
+                };
+            } else if let Some(tail) = rest.strip_prefix('.') {
+                if first {
+                    return Err("must not start with '.'".to_string());
+                }
+                rest = parse_member(tail, &mut segments)?;
+            } else if rest.starts_with('[') {
+                rest = parse_bracket(rest, &mut segments)?;
+            } else if first {
+                rest = parse_member(rest, &mut segments)?;
+            } else {
+                return Err(format!("unexpected '{}'", rest));
+            }
+            first = false;
+        }
+
+        Ok(Keypath {
+            segments,
+            disclosure,
+        })
+    }
+}
+
+/// Parses a member name up to the next `.` or `[`.
+fn parse_member<'a>(rest: &'a str, segments: &mut Vec<Segment>) -> Result<&'a str, String> {
+    let end = rest.find(['.', '[']).unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(end);
+    if name.is_empty() {
+        return Err("contains an empty segment".to_string());
+    }
+    if name.contains(']') {
+        return Err(format!("unexpected ']' in '{}'", name));
+    }
+
+    segments.push(match name {
+        "*" => Segment::Wildcard,
+        _ => Segment::Key(name.to_string()),
+    });
+    Ok(rest)
+}
+
+/// Parses `[n]`, `[*]`, `["name"]` or `['name']`.
+fn parse_bracket<'a>(rest: &'a str, segments: &mut Vec<Segment>) -> Result<&'a str, String> {
+    let inner = &rest[1..];
+
+    if let Some(quote) = inner.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let name_end = inner[1..]
+            .find(quote)
+            .ok_or_else(|| format!("unterminated {} in brackets", quote))?;
+        let name = &inner[1..1 + name_end];
+        let rest = inner[1 + name_end + 1..]
+            .strip_prefix(']')
+            .ok_or("expected ']' after quoted name")?;
+        segments.push(Segment::Key(name.to_string()));
+        return Ok(rest);
+    }
+
+    let end = inner.find(']').ok_or("unterminated '['")?;
+    let segment = match &inner[..end] {
+        "*" => Segment::Wildcard,
+        index => Segment::Index(
+            index
+                .parse()
+                .map_err(|_| format!("'{}' is not an array index", index))?,
+        ),
+    };
+    segments.push(segment);
+    Ok(&inner[end + 1..])
+}
+
+impl Keypath {
+    pub fn disclosure(&self) -> Disclosure {
+        self.disclosure
+    }
+
+    /// Returns the values the keypath selects in `content`, each at most once.
+    /// Where an object repeats a key the path names, `occurrence` picks which
+    /// of the members are followed.
+    pub fn select<'a>(
+        &self,
+        content: &'a RangedValue,
+        occurrence: Occurrence,
+    ) -> Vec<Selected<'a>> {
+        let root = Selected {
+            key_range: None,
+            value: content,
+            parent: None,
+        };
+        let mut selected = Vec::new();
+        select(&self.segments, root, occurrence, &mut selected);
+
+        // Recursive descent may reach a value on more than one way.
+        let mut unique: Vec<Selected<'a>> = Vec::with_capacity(selected.len());
+        for found in selected {
+            if !unique
+                .iter()
+                .any(|seen| std::ptr::eq(seen.value, found.value))
+            {
+                unique.push(found);
+            }
+        }
+        unique
+    }
+}
+
+fn select<'a>(
+    segments: &[Segment],
+    current: Selected<'a>,
+    occurrence: Occurrence,
+    selected: &mut Vec<Selected<'a>>,
+) {
+    let Some((segment, rest)) = segments.split_first() else {
+        selected.push(current);
+        return;
+    };
+
+    let value = current.value;
+    let member = |member: &'a RangedMember| Selected {
+        key_range: Some(&member.key_range),
+        value: &member.value,
+        parent: Some(value),
+    };
+    let item = |item: &'a RangedValue| Selected {
+        key_range: None,
+        value: item,
+        parent: Some(value),
+    };
+
+    match (segment, value) {
+        (Segment::Key(name), RangedValue::Object { value: members, .. }) => {
+            for found in members.get(name, occurrence) {
+                select(rest, member(found), occurrence, selected);
+            }
+        }
+        // Arrays are transparent to member names.
+        (Segment::Key(_), RangedValue::Array { value: items, .. }) => {
+            for found in items {
+                select(segments, item(found), occurrence, selected);
+            }
+        }
+        (Segment::Index(index), RangedValue::Array { value: items, .. }) => {
+            if let Some(found) = items.get(*index) {
+                select(rest, item(found), occurrence, selected);
+            }
+        }
+        (Segment::Wildcard, RangedValue::Object { value: members, .. }) => {
+            for found in members.values() {
+                select(rest, member(found), occurrence, selected);
+            }
+        }
+        (Segment::Wildcard, RangedValue::Array { value: items, .. }) => {
+            for found in items {
+                select(rest, item(found), occurrence, selected);
+            }
+        }
+        (Segment::Descendants, _) => {
+            select(rest, current, occurrence, selected);
+            match value {
+                RangedValue::Object { value: members, .. } => {
+                    for found in members.values() {
+                        select(segments, member(found), occurrence, selected);
+                    }
+                }
+                RangedValue::Array { value: items, .. } => {
+                    for found in items {
+                        select(segments, item(found), occurrence, selected);
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pest::Parser;
+
+    use super::*;
+    use crate::response::{parse_response_value, ResponseParser, Rule};
+
+    fn parse(json: &str) -> RangedValue {
+        let pair = ResponseParser::parse(Rule::json_body, json)
+            .unwrap()
+            .next()
+            .unwrap();
+        parse_response_value(pair).unwrap()
+    }
+
+    /// Text of the values `keypath` selects in `json`.
+    fn select<'a>(json: &'a str, keypath: &str, occurrence: Occurrence) -> Vec<&'a str> {
+        let keypath: Keypath = keypath.parse().unwrap();
+        keypath
+            .select(&parse(json), occurrence)
+            .iter()
+            .map(|selected| &json[selected.value.get_range()])
+            .collect()
+    }
+
+    #[test]
+    fn parses_segments_and_prefixes() {
+        let keypath: Keypath = r#"value:data["a.b"][0]..*"#.parse().unwrap();
+        assert_eq!(keypath.disclosure(), Disclosure::Value);
+        assert_eq!(
+            keypath.segments,
+            vec![
+                Segment::Key("data".to_string()),
+                Segment::Key("a.b".to_string()),
+                Segment::Index(0),
+                Segment::Descendants,
+                Segment::Wildcard,
+            ]
+        );
+
+        let keypath: Keypath = "..[2]".parse().unwrap();
+        assert_eq!(keypath.disclosure(), Disclosure::Pair);
+        assert_eq!(
+            keypath.segments,
+            vec![Segment::Descendants, Segment::Index(2)]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_keypaths() {
+        for keypath in [
+            "", ".a", "a.", "a..", "a..b.", "a[", "a[x]", "a[-1]", "a]", r#"a["b]"#, r#"a["b""#,
+            "a[0]b",
+        ] {
+            assert!(
+                keypath.parse::<Keypath>().is_err(),
+                "'{}' should be rejected",
+                keypath
+            );
+        }
+    }
+
+    #[test]
+    fn selects_array_elements() {
+        let json = r#"{"transactions":[{"id":1,"amount":10},{"id":2,"amount":20}]}"#;
+
+        assert_eq!(
+            select(json, "transactions[1].amount", Occurrence::All),
+            vec!["20"]
+        );
+        assert_eq!(
+            select(json, "transactions[*].id", Occurrence::All),
+            vec!["1", "2"]
+        );
+        assert_eq!(
+            select(json, "transactions.id", Occurrence::All),
+            vec!["1", "2"]
+        );
+        assert!(select(json, "transactions[2].id", Occurrence::All).is_empty());
+        assert!(select(json, "transactions[0][0]", Occurrence::All).is_empty());
+    }
+
+    #[test]
+    fn selects_at_any_depth_once() {
+        let json = r#"{"id":0,"a":{"id":1,"b":[{"id":2},[{"id":3}]]}}"#;
+
+        assert_eq!(
+            select(json, "..id", Occurrence::All),
+            vec!["0", "1", "2", "3"]
+        );
+        assert_eq!(select(json, "a..id", Occurrence::All), vec!["1", "2", "3"]);
+        assert_eq!(
+            select(json, "a.b..[0]", Occurrence::All),
+            vec![r#"{"id":2}"#, r#"{"id":3}"#]
+        );
+    }
+
+    #[test]
+    fn follows_repeated_keys_by_occurrence() {
+        let json = r#"{"a":{"v":1},"a":{"v":2}}"#;
+
+        assert_eq!(select(json, "a.v", Occurrence::First), vec!["1"]);
+        assert_eq!(select(json, "a.v", Occurrence::Last), vec!["2"]);
+        assert_eq!(select(json, "a.v", Occurrence::All), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn discloses_from_the_actual_key_span() {
+        let json = "{ \"user\" :\t{\"name\"  :  \"Ada\", \"tags\": [\"x\"] } }";
+        let content = parse(json);
+        let disclosed = |keypath: &str| -> Vec<Option<&str>> {
+            let keypath: Keypath = keypath.parse().unwrap();
+            keypath
+                .select(&content, Occurrence::All)
+                .iter()
+                .map(|selected| {
+                    selected
+                        .disclosed_range(keypath.disclosure())
+                        .map(|range| &json[range])
+                })
+                .collect()
+        };
+
+        assert_eq!(disclosed("user.name"), vec![Some("\"name\"  :  \"Ada\"")]);
+        assert_eq!(disclosed("value:user.name"), vec![Some("\"Ada\"")]);
+        assert_eq!(disclosed("key:user.name"), vec![Some("\"name\"")]);
+        assert_eq!(
+            disclosed("object:user.name"),
+            vec![Some("{\"name\"  :  \"Ada\", \"tags\": [\"x\"] }")]
+        );
+
+        // Array elements have no key of their own.
+        assert_eq!(disclosed("user.tags[0]"), vec![Some("\"x\"")]);
+        assert_eq!(disclosed("key:user.tags[0]"), vec![None]);
+        assert_eq!(disclosed("object:user.tags[0]"), vec![Some("[\"x\"]")]);
+    }
+
+    #[test]
+    fn reveals_null_values_at_their_span() {
+        let json = r#"{"b": 1, "a": null}"#;
+        let content = parse(json);
+        let disclosed = |keypath: &str| -> Vec<&str> {
+            let keypath: Keypath = keypath.parse().unwrap();
+            keypath
+                .select(&content, Occurrence::All)
+                .iter()
+                .filter_map(|selected| selected.disclosed_range(keypath.disclosure()))
+                .map(|range| &json[range])
+                .collect()
+        };
+
+        assert_eq!(disclosed("value:a"), vec!["null"]);
+        assert_eq!(disclosed("a"), vec![r#""a": null"#]);
+    }
+}
@@ -1,30 +1,90 @@
-use std::net::SocketAddr;
+use std::{
+    convert::Infallible, net::SocketAddr, num::NonZeroUsize, path::PathBuf, process::ExitCode,
+    time::Duration,
+};
 
-use clap::Parser;
-use communication::MessageType;
-use http_body_util::Empty;
+use clap::{Parser, Subcommand, ValueEnum};
+use communication::{
+    Output, MAX_INBOUND_MESSAGE_SIZE, MAX_REQUEST_ID_LENGTH, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use errors::{Direction, Errors, Stage};
+use http_body_util::Full;
 use hyper::{
-    body::Bytes,
+    body::{Body, Bytes},
     header::{HeaderName, HeaderValue},
-    Request, Uri,
+    Method, Request, StatusCode,
+};
+use messages::{
+    CancelRequest, HelloResponse, InboundMessage, InboundRequest, Outcome, Progress, ProofRequest,
+    ProtocolRange,
 };
+use policy::RedactionPolicy;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpStream;
+use serde_json::Value;
+use session::{Finish, Sessions};
+use tokio::{net::TcpStream, task::JoinHandle};
 use url::Url;
-use utils::prover;
+use utils::{prover, ProofMode, ProofOutcome};
 
 mod ast;
+mod body;
+mod bundle;
 mod communication;
 mod errors;
+mod keypath;
+mod messages;
+mod notarize;
+mod policy;
 mod request;
 mod response;
+mod session;
 mod utils;
 
-#[derive(Parser, Debug, Deserialize, Serialize, Clone)]
+#[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a single proof with arguments taken from the command line.
+    Prove(Box<Args>),
+    /// Serve the Chrome extension over native messaging on stdin/stdout (default).
+    NativeHost(NativeHostArgs),
+    /// Print the JSON Schema of the messages exchanged with the extension.
+    Schema(SchemaArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct NativeHostArgs {
+    /// How many proving sessions run at the same time; further requests are queued.
+    #[arg(long, env = "KRITHON_MAX_SESSIONS", default_value = "2")]
+    max_sessions: NonZeroUsize,
+}
+
+#[derive(clap::Args, Debug)]
+struct SchemaArgs {
+    /// Write the schema to this file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Deserialize, Serialize, JsonSchema, Clone)]
 struct Args {
     server_uri: Url,
 
+    #[arg(short = 'X', long, default_value = "GET")]
+    #[serde(default = "default_method")]
+    method: String,
+
+    /// JSON body sent with the request, e.g. for POST/PUT/PATCH.
+    #[arg(short, long, value_parser = parse_json_body)]
+    #[serde(default)]
+    body: Option<Value>,
+
     #[arg(short, long, default_value = "127.0.0.1:8079")]
     verifier_address: SocketAddr,
 
@@ -36,102 +96,533 @@ struct Args {
 
     #[arg(long, default_value = "16384")]
     max_recv_data: usize,
+
+    /// Response status codes treated as success. An empty list accepts any status.
+    #[arg(long = "accept-status", default_value = "200")]
+    #[serde(default = "default_accepted_statuses")]
+    accepted_statuses: Vec<u16>,
+
+    /// Treat every response status as success. Only a command-line shorthand:
+    /// the extension sends an empty list of accepted statuses instead.
+    #[arg(long, conflicts_with = "accepted_statuses")]
+    #[serde(skip)]
+    accept_any_status: bool,
+
+    /// Interactive proof to the verifier, or notarization into a signed attestation.
+    #[arg(long, value_enum, default_value_t = ProofMode::Interactive)]
+    #[serde(default)]
+    mode: ProofMode,
+
+    /// Directory the session bundle is written to after a successful proof.
+    #[arg(long)]
+    #[serde(default)]
+    bundle_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    #[serde(default)]
+    policy: RedactionPolicy,
+}
+
+/// How long shutdown waits for queued frames to reach the extension.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Version of the tlsn crates this host is built against, taken from the
+/// Cargo.toml tag by build.rs.
+const TLSN_VERSION: &str = env!("TLSN_VERSION");
+
+/// Methods the request grammar can parse back out of the sent transcript.
+const SUPPORTED_METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+fn default_method() -> String {
+    Method::GET.to_string()
+}
+
+fn default_accepted_statuses() -> Vec<u16> {
+    vec![StatusCode::OK.as_u16()]
+}
+
+impl Args {
+    /// Whether a response with `status` counts as success.
+    fn accepts_status(&self, status: u16) -> bool {
+        self.accept_any_status
+            || self.accepted_statuses.is_empty()
+            || self.accepted_statuses.contains(&status)
+    }
+}
+
+fn parse_json_body(body: &str) -> Result<Value, serde_json::Error> {
+    serde_json::from_str(body)
 }
 
 #[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    let mut stdout: tokio::io::Stdout = tokio::io::stdout();
+async fn main() -> ExitCode {
+    // Chrome starts the host with the caller origin as the only argument.
+    // Without a subcommand we serve the extension as well.
+    let cli = if launched_by_chrome() || std::env::args_os().len() == 1 {
+        Cli::parse_from(["krithon-prover", "native-host"])
+    } else {
+        Cli::parse()
+    };
+
+    match cli.command {
+        Command::Prove(args) => prove(*args).await,
+        Command::NativeHost(args) => match native_host(args).await {},
+        Command::Schema(args) => schema(args),
+    }
+}
+
+fn launched_by_chrome() -> bool {
+    std::env::args()
+        .nth(1)
+        .is_some_and(|arg| arg.starts_with("chrome-extension://"))
+}
+
+/// Runs one proof from the command line and reports the outcome as the exit status.
+async fn prove(args: Args) -> ExitCode {
+    let mut output = Output::console();
+
+    match run(args, &mut output).await {
+        Ok(outcome) => {
+            communication::send_done(Outcome::Proof(outcome), &mut output).await;
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            communication::send_failed(&e, &mut output).await;
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Serves proof requests from the extension until it disconnects or the host
+/// receives SIGTERM, then exits with status 0. Exits with status 1 when stdin
+/// can no longer be read.
+async fn native_host(host_args: NativeHostArgs) -> Infallible {
+    let (output, writer) = Output::native_host();
+    let sessions = Sessions::new(host_args.max_sessions.get());
     let mut stdin: tokio::io::Stdin = tokio::io::stdin();
+    let mut terminated = std::pin::pin!(terminated());
 
-    loop {
-        let message_string = communication::read_message(&mut stdin).await;
-        let test_message = serde_json::json!({
-            "message": "Message received",
-        });
-        communication::send_response(test_message, MessageType::Message, &mut stdout).await;
-        match message_string {
-            Ok(message) => {
-                let args: Args = match serde_json::from_str(&message) {
-                    Ok(args) => args,
-                    Err(e) => {
-                        communication::send_error_response(&format!("Failed to parse arguments: {}", e), &mut stdout).await;
-                        continue;
-                    }
-                };
-
-                let host = match args.server_uri.host() {
-                    Some(host) => host.to_owned(),
-                    None => {
-                        communication::send_error_response(&format!("Server URI does not have a host"), &mut stdout).await;
-                        continue;
-                    }
-                };
-
-                let mut request = match Request::builder()
-                    .method("GET")
-                    .uri(args.server_uri.as_str())
-                    .header("connection", "close")
-                    .header("host", host.to_string())
-                    .body(Empty::<Bytes>::new()) 
-                {
-                    Ok(req) => req,
-                    Err(e) => {
-                        communication::send_error_response(&format!("Failed to build request: {}", e), &mut stdout).await;
-                        continue;
-                    }
-                };
-
-                let request_headers = request.headers_mut();
-
-                for header in args.headers {
-                    // Split headers in the format "Key: Value"
-                    if let Some((key, value)) = header.split_once(':') {
-                        let key = match key.trim().parse::<HeaderName>() {
-                            Ok(k) => k,
-                            Err(e) => {
-                                communication::send_error_response(&format!("Invalid header name '{}': {}", key, e), &mut stdout).await;
-                                continue;
-                            }
-                        };
-                        let value = match value.trim().parse::<HeaderValue>() {
-                            Ok(v) => v,
-                            Err(e) => {
-                                communication::send_error_response(&format!("Invalid header value '{}': {}", value, e), &mut stdout).await;
-                                continue;
-                            }
-                        };
-                        request_headers.insert(key, value);
-                    } else {
-                        communication::send_error_response(&format!("Header '{}' is not in 'Key: Value' format", header), &mut stdout).await;
-                        continue;
-                    }
-                }
-                communication::logging_message(&mut stdout, "Request headers done").await;
+    let exit_code = loop {
+        let read = tokio::select! {
+            read = communication::read_message(&mut stdin) => read,
+            _ = &mut terminated => break 0,
+        };
+        let message = match read {
+            Ok(Some(message)) => message,
+            Ok(None) => break 0,
+            // The oversized payload was skipped, the next frame is readable.
+            Err(e @ Errors::MessageTooLarge { .. }) => {
+                communication::send_error(&e, &mut output.clone()).await;
+                continue;
+            }
+            Err(e) => {
+                communication::send_error(&e, &mut output.clone()).await;
+                break 1;
+            }
+        };
 
-                let socket = match TcpStream::connect(args.verifier_address).await {
-                    Ok(s) => s,
-                    Err(e) => {
-                        communication::send_error_response(&format!("Failed to connect to verifier: {}", e), &mut stdout).await;
-                        continue;
-                    }
-                };
+        let request: ProofRequest = match parse_message(&message) {
+            Ok(InboundRequest::Prove(request)) => *request,
+            Ok(InboundRequest::Cancel(request)) => {
+                cancel(request, &sessions, &output).await;
+                continue;
+            }
+            Ok(InboundRequest::Hello(request)) => {
+                let mut output = output.with_id(request.id);
+                communication::send_done(Outcome::Hello(hello()), &mut output).await;
+                continue;
+            }
+            Err(e) => {
+                // Still correlate the failure when at least the id is readable.
+                let mut output = output.with_id(request_id(&message));
+                communication::send_failed(&e, &mut output).await;
+                continue;
+            }
+        };
 
-                communication::logging_message(&mut stdout, "Connecting to verifier").await;
+        let mut session_output = output.with_id(Some(request.id.clone()));
+        communication::send_message("Message received", &mut session_output).await;
 
-                if let Err(e) = prover(socket, request, args.max_sent_data, args.max_recv_data, &mut stdout).await {
-                    communication::send_error_response( &format!("Prover encountered an error: {}", e), &mut stdout).await;
-                    continue;
+        let mut reply_output = session_output.clone();
+        let mut panic_output = session_output.clone();
+        let session = move |finish: Finish| async move {
+            let result = run(request.args, &mut session_output).await;
+            // A cancel that got in first has sent `Cancelled` already.
+            if !finish.claim() {
+                return;
+            }
+            match result {
+                Ok(outcome) => {
+                    communication::send_done(Outcome::Proof(outcome), &mut session_output).await
                 }
+                Err(e) => communication::send_failed(&e, &mut session_output).await,
+            }
+        };
+        let on_panic = async move {
+            communication::send_failed(&Errors::SessionPanicked, &mut panic_output).await
+        };
+        let spawned = sessions.spawn(request.id, session, on_panic);
+        // The session already using this id sends the terminal frame for it.
+        if let Err(e) = spawned {
+            communication::send_error(&e, &mut reply_output).await;
+        }
+    };
+
+    shut_down(sessions, output, writer).await;
+    // stdin is read on a blocking thread that cannot be interrupted, and the
+    // runtime would wait for it when dropped. Exit right away instead.
+    std::process::exit(exit_code)
+}
+
+/// Cancels all sessions and waits until their frames reach stdout.
+async fn shut_down(sessions: Sessions, output: Output, writer: JoinHandle<()>) {
+    for id in sessions.cancel_all() {
+        communication::send_cancelled(&mut output.with_id(Some(id))).await;
+    }
+
+    // The writer finishes once the aborted sessions dropped their outputs.
+    drop(output);
+    let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, writer).await;
+}
+
+/// Resolves when the host is asked to terminate.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut sigterm) = signal(SignalKind::terminate()) {
+            sigterm.recv().await;
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
 
-                communication::logging_message(&mut stdout, "Prover done successfully").await;
+/// Parses a message from the extension and checks its protocol version.
+fn parse_message(message: &str) -> Result<InboundRequest, Errors> {
+    let mut value: Value = serde_json::from_str(message).map_err(Errors::InvalidMessage)?;
+    // Messages without a `type` are proof requests.
+    if let Value::Object(fields) = &mut value {
+        fields.entry("type").or_insert_with(|| "prove".into());
+    }
 
+    match InboundMessage::deserialize(&value) {
+        Ok(message) => {
+            check_protocol_version(message.protocol_version)?;
+            if let Some(id) = message.request.id() {
+                if id.len() > MAX_REQUEST_ID_LENGTH {
+                    return Err(Errors::RequestIdTooLong {
+                        length: id.len(),
+                        limit: MAX_REQUEST_ID_LENGTH,
+                    });
+                }
             }
+            Ok(message.request)
+        }
+        Err(e) => {
+            // Messages of a newer protocol may not parse at all, report the
+            // version mismatch rather than the parse error then.
+            let requested = value
+                .get("protocol_version")
+                .and_then(Value::as_u64)
+                .map(|version| u32::try_from(version).unwrap_or(u32::MAX));
+            check_protocol_version(requested)?;
+            Err(Errors::InvalidMessage(e))
+        }
+    }
+}
+
+fn check_protocol_version(requested: Option<u32>) -> Result<(), Errors> {
+    match requested {
+        Some(requested) if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&requested) => {
+            Err(Errors::UnsupportedProtocolVersion {
+                requested,
+                min: MIN_PROTOCOL_VERSION,
+                max: PROTOCOL_VERSION,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Aborts the session named by a cancel message and sends its terminal frame.
+async fn cancel(request: CancelRequest, sessions: &Sessions, output: &Output) {
+    let mut output = output.with_id(Some(request.id.clone()));
+    match sessions.cancel(&request.id) {
+        Ok(()) => communication::send_cancelled(&mut output).await,
+        Err(e) => communication::send_error(&e, &mut output).await,
+    }
+}
+
+/// Describes what this host supports, so the extension can detect version drift.
+fn hello() -> HelloResponse {
+    HelloResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol: ProtocolRange {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        },
+        tlsn_version: TLSN_VERSION.to_string(),
+        methods: SUPPORTED_METHODS.iter().map(Method::to_string).collect(),
+        modes: ProofMode::value_variants().to_vec(),
+        max_message_size: MAX_INBOUND_MESSAGE_SIZE,
+    }
+}
+
+/// Prints the JSON Schema of the native-messaging messages.
+fn schema(args: SchemaArgs) -> ExitCode {
+    let schemas = match serde_json::to_string_pretty(&messages::schemas()) {
+        Ok(schemas) => schemas,
+        Err(e) => {
+            eprintln!("Failed to serialize schema: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match args.output {
+        Some(path) => match std::fs::write(&path, schemas) {
+            Ok(()) => ExitCode::SUCCESS,
             Err(e) => {
-                communication::send_error_response(&e.to_string(), &mut stdout).await;
-                continue;
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                ExitCode::FAILURE
             }
+        },
+        None => {
+            println!("{}", schemas);
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/// Extracts the `id` of a message that otherwise failed to parse.
+fn request_id(message: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(message).ok()?;
+    let id = value.get("id")?.as_str()?;
+    // An id too long to echo is left out rather than cut.
+    (id.len() <= MAX_REQUEST_ID_LENGTH).then(|| id.to_string())
+}
+
+/// Builds the request described by `args` and proves it to the verifier.
+async fn run(args: Args, output: &mut Output) -> Result<ProofOutcome, Errors> {
+    output.set_stage(Stage::Validation);
+    args.policy.validate()?;
+    if let Some(&status) = args
+        .accepted_statuses
+        .iter()
+        .find(|&&status| StatusCode::from_u16(status).is_err())
+    {
+        return Err(Errors::InvalidStatusCode(status));
+    }
+
+    output.set_stage(Stage::Request);
+    let request = build_request(&args)?;
+    communication::logging_message(output, "Request headers done").await;
+
+    output.set_stage(Stage::VerifierConnection);
+    let socket = TcpStream::connect(args.verifier_address)
+        .await
+        .map_err(Errors::VerifierConnectionError)?;
+
+    communication::send_progress(Progress::VerifierConnected, output).await;
+
+    let outcome = prover(socket, request, &args, output).await?;
+
+    communication::logging_message(output, "Prover done successfully").await;
+
+    Ok(outcome)
+}
+
+fn build_request(args: &Args) -> Result<Request<Full<Bytes>>, Errors> {
+    let host = args.server_uri.host().ok_or(Errors::MissingAuthority)?;
+
+    let method = match args.method.to_uppercase().parse::<Method>() {
+        Ok(method) if SUPPORTED_METHODS.contains(&method) => method,
+        _ => return Err(Errors::UnsupportedMethod(args.method.clone())),
+    };
+
+    // The request grammar only understands JSON objects and arrays as a body.
+    let body = match &args.body {
+        Some(body @ (Value::Object(_) | Value::Array(_))) => {
+            Bytes::from(serde_json::to_vec(body).map_err(|e| Errors::InvalidBody(e.to_string()))?)
+        }
+        Some(_) => {
+            return Err(Errors::InvalidBody(
+                "Request body must be a JSON object or array".to_string(),
+            ))
+        }
+        None => Bytes::new(),
+    };
+
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(args.server_uri.as_str())
+        .header("connection", "close")
+        .header("host", host.to_string());
+    if !body.is_empty() {
+        builder = builder.header("content-type", "application/json");
+    }
+
+    let mut request = builder.body(Full::new(body))?;
+
+    let request_headers = request.headers_mut();
+
+    for header in &args.headers {
+        // Split headers in the format "Key: Value"
+        let (key, value) = header.split_once(':').ok_or_else(|| {
+            Errors::InvalidHeader(format!("Header '{}' is not in 'Key: Value' format", header))
+        })?;
+        let key = key
+            .trim()
+            .parse::<HeaderName>()
+            .map_err(|e| Errors::InvalidHeader(format!("Invalid header name '{}': {}", key, e)))?;
+        let value = value.trim().parse::<HeaderValue>().map_err(|e| {
+            Errors::InvalidHeader(format!("Invalid header value '{}': {}", value, e))
+        })?;
+        request_headers.insert(key, value);
+    }
+
+    // Catch requests the verifier would reject only after the whole setup phase.
+    let required = estimated_size(&request);
+    if required > args.max_sent_data {
+        return Err(Errors::LimitsExceeded {
+            direction: Direction::Sent,
+            limit: args.max_sent_data,
+            required,
+        });
+    }
+
+    Ok(request)
+}
+
+/// Lower bound of the bytes `request` takes on the wire. Hyper may add
+/// headers such as `content-length` on top.
+fn estimated_size(request: &Request<Full<Bytes>>) -> usize {
+    let target = request
+        .uri()
+        .path_and_query()
+        .map_or(1, |path_and_query| path_and_query.as_str().len());
+    // "METHOD TARGET HTTP/1.1\r\n"
+    let request_line = request.method().as_str().len() + 1 + target + " HTTP/1.1\r\n".len();
+    // "name: value\r\n" per header, then the empty line.
+    let headers: usize = request
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + 2 + value.len() + 2)
+        .sum::<usize>()
+        + 2;
+
+    request_line + headers + request.body().size_hint().exact().unwrap_or(0) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn prove_message(id: &str) -> Value {
+        json!({
+            "id": id,
+            "server_uri": "https://api.example.com/v1/me",
+            "verifier_address": "127.0.0.1:8079",
+            "headers": ["Accept: application/json"],
+            "max_sent_data": 4096,
+            "max_recv_data": 16384,
+        })
+    }
+
+    #[test]
+    fn parses_a_proof_request_without_type() {
+        let Ok(InboundRequest::Prove(request)) = parse_message(&prove_message("1").to_string())
+        else {
+            panic!("expected a proof request");
         };
+        assert_eq!(request.id, "1");
+        assert_eq!(request.args.method, "GET");
+        assert_eq!(request.args.accepted_statuses, vec![200]);
+        assert_eq!(request.args.mode, ProofMode::Interactive);
     }
 
-    Ok(())
+    #[test]
+    fn proof_request_args_round_trip() {
+        let mut message = prove_message("1");
+        message["method"] = "POST".into();
+        message["body"] = json!({ "name": "value" });
+        message["accepted_statuses"] = json!([200, 201]);
+        message["mode"] = "notarize".into();
+        message["policy"] = json!({ "response_keypaths": ["user.name"] });
+        let Ok(InboundRequest::Prove(request)) = parse_message(&message.to_string()) else {
+            panic!("expected a proof request");
+        };
+
+        let mut echoed = serde_json::to_value(&request.args).unwrap();
+        echoed["id"] = "1".into();
+        let Ok(InboundRequest::Prove(reparsed)) = parse_message(&echoed.to_string()) else {
+            panic!("expected a proof request");
+        };
+        assert_eq!(
+            serde_json::to_value(&reparsed.args).unwrap(),
+            serde_json::to_value(&request.args).unwrap()
+        );
+        assert_eq!(reparsed.args.policy.response_keypaths, vec!["user.name"]);
+    }
+
+    #[test]
+    fn parses_cancel_and_hello() {
+        let cancel = parse_message(r#"{"type":"cancel","id":"1"}"#);
+        assert!(matches!(cancel, Ok(InboundRequest::Cancel(request)) if request.id == "1"));
+
+        let hello = parse_message(r#"{"type":"hello","protocol_version":1}"#);
+        assert!(matches!(hello, Ok(InboundRequest::Hello(request)) if request.id.is_none()));
+    }
+
+    #[test]
+    fn rejects_unsupported_protocol_versions() {
+        let mut message = prove_message("1");
+        message["protocol_version"] = (PROTOCOL_VERSION + 1).into();
+        let parsed = parse_message(&message.to_string());
+        assert!(matches!(
+            parsed,
+            Err(Errors::UnsupportedProtocolVersion { requested, .. })
+                if requested == PROTOCOL_VERSION + 1
+        ));
+
+        // A message of a newer protocol that does not parse still reports the version.
+        let parsed = parse_message(r#"{"type":"subscribe","protocol_version":99}"#);
+        assert!(matches!(
+            parsed,
+            Err(Errors::UnsupportedProtocolVersion { requested: 99, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        assert!(matches!(
+            parse_message("not json"),
+            Err(Errors::InvalidMessage(_))
+        ));
+        assert!(matches!(
+            parse_message(r#"{"type":"cancel"}"#),
+            Err(Errors::InvalidMessage(_))
+        ));
+        assert_eq!(request_id(r#"{"id":"7","server_uri":1}"#), Some("7".into()));
+    }
+
+    #[test]
+    fn rejects_request_ids_that_are_too_long() {
+        let id = "x".repeat(MAX_REQUEST_ID_LENGTH + 1);
+        let parsed = parse_message(&prove_message(&id).to_string());
+        assert!(matches!(parsed, Err(Errors::RequestIdTooLong { .. })));
+        assert_eq!(request_id(&prove_message(&id).to_string()), None);
+
+        let id = "x".repeat(MAX_REQUEST_ID_LENGTH);
+        assert!(parse_message(&prove_message(&id).to_string()).is_ok());
+    }
 }
@@ -0,0 +1,331 @@
+use schemars::{
+    schema::{RootSchema, Schema},
+    schema_for, JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ErrorReport;
+use crate::utils::{ProofMode, ProofOutcome};
+use crate::Args;
+
+/// A message from the extension.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct InboundMessage {
+    /// Protocol version the extension speaks. Older extensions do not send
+    /// it and are served as the current version.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    #[serde(flatten)]
+    pub request: InboundRequest,
+}
+
+/// Kind of an incoming message, given by its `type` field. Messages without
+/// a `type` are proof requests.
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum InboundRequest {
+    /// Start a proof.
+    Prove(Box<ProofRequest>),
+    /// Cancel the proof running under `id`.
+    Cancel(CancelRequest),
+    /// Ask which protocol versions and features this host supports.
+    Hello(HelloRequest),
+}
+
+/// A proof request. Every frame sent for it echoes `id`.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ProofRequest {
+    pub id: String,
+    #[serde(flatten)]
+    pub args: Args,
+}
+
+impl InboundRequest {
+    /// Id the request names, if any.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            InboundRequest::Prove(request) => Some(&request.id),
+            InboundRequest::Cancel(request) => Some(&request.id),
+            InboundRequest::Hello(request) => request.id.as_deref(),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct CancelRequest {
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct HelloRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// Type of a frame sent to the extension.
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy)]
+pub enum MessageType {
+    Message,
+    Logging,
+    Error,
+    /// Terminal frame of a request that finished successfully.
+    Done,
+    /// Terminal frame of a request that failed.
+    Failed,
+    /// Terminal frame of a request cancelled by the extension.
+    Cancelled,
+    /// A milestone of the proving session, see `Progress`.
+    Progress,
+}
+
+/// A frame sent to the extension: `{"type", "id", "message"}`.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Frame {
+    /// Id of the request the frame belongs to, `null` when it is unknown.
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub body: FrameBody,
+}
+
+/// The `type` of a frame together with its `message`.
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(tag = "type", content = "message")]
+pub enum FrameBody {
+    Message(Notice),
+    Logging(LogMessage),
+    Error(ErrorReport),
+    Done(Outcome),
+    /// Carries the same report as the `Error` frame sent right before it.
+    Failed(ErrorReport),
+    Cancelled(Notice),
+    Progress(ProgressReport),
+}
+
+impl FrameBody {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            FrameBody::Message(_) => MessageType::Message,
+            FrameBody::Logging(_) => MessageType::Logging,
+            FrameBody::Error(_) => MessageType::Error,
+            FrameBody::Done(_) => MessageType::Done,
+            FrameBody::Failed(_) => MessageType::Failed,
+            FrameBody::Cancelled(_) => MessageType::Cancelled,
+            FrameBody::Progress(_) => MessageType::Progress,
+        }
+    }
+}
+
+/// One of the frames a message too large for a single frame is split into.
+/// Concatenating `data` of all `count` frames and parsing it yields the
+/// `message` of the original frame, whose type is `original_type`.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ContinuationFrame<'a> {
+    #[serde(rename = "type")]
+    pub frame_type: ContinuationType,
+    pub original_type: MessageType,
+    pub id: Option<&'a str>,
+    pub chunk: Chunk,
+    pub data: &'a str,
+}
+
+/// `type` of every continuation frame, so it cannot be mistaken for a
+/// complete frame of the original type.
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy)]
+pub enum ContinuationType {
+    Continuation,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Chunk {
+    pub index: usize,
+    pub count: usize,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Notice {
+    pub message: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct LogMessage {
+    pub logging: String,
+}
+
+/// Milestones of a proving session, in the order they are reached.
+/// Notarization reports `TranscriptCommitted` in place of `TranscriptProved`,
+/// so both modes go through every step.
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Progress {
+    VerifierConnected,
+    SetupDone,
+    ServerConnected,
+    RequestSent,
+    ResponseReceived {
+        status: u16,
+    },
+    TranscriptProved,
+    /// The revealed ranges were committed to for the attestation.
+    TranscriptCommitted,
+    Finalized,
+}
+
+impl Progress {
+    pub const TOTAL_STEPS: usize = 7;
+
+    /// 1-based position of the milestone, for rendering a progress bar.
+    pub fn step(&self) -> usize {
+        match self {
+            Progress::VerifierConnected => 1,
+            Progress::SetupDone => 2,
+            Progress::ServerConnected => 3,
+            Progress::RequestSent => 4,
+            Progress::ResponseReceived { .. } => 5,
+            Progress::TranscriptProved | Progress::TranscriptCommitted => 6,
+            Progress::Finalized => 7,
+        }
+    }
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Progress::VerifierConnected => write!(f, "Connected to verifier"),
+            Progress::SetupDone => write!(f, "Setup with verifier done"),
+            Progress::ServerConnected => write!(f, "Connected to server"),
+            Progress::RequestSent => write!(f, "Request sent"),
+            Progress::ResponseReceived { status } => {
+                write!(f, "Response received with status {}", status)
+            }
+            Progress::TranscriptProved => write!(f, "Transcript proved"),
+            Progress::TranscriptCommitted => write!(f, "Transcript committed"),
+            Progress::Finalized => write!(f, "Session finalized"),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ProgressReport {
+    #[serde(flatten)]
+    pub event: Progress,
+    pub step: usize,
+    pub total_steps: usize,
+    /// Milliseconds since the request was received.
+    pub elapsed_ms: u64,
+}
+
+/// Payload of a `Done` frame.
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum Outcome {
+    Proof(ProofOutcome),
+    Hello(HelloResponse),
+}
+
+/// What this host supports, so the extension can detect version drift.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct HelloResponse {
+    pub version: String,
+    pub protocol: ProtocolRange,
+    pub tlsn_version: String,
+    pub methods: Vec<String>,
+    pub modes: Vec<ProofMode>,
+    /// Largest message the host accepts, in bytes.
+    pub max_message_size: usize,
+}
+
+/// Protocol versions the host serves, both inclusive.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct ProtocolRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// JSON Schemas of everything exchanged with the extension.
+#[derive(Serialize, Debug)]
+pub struct MessageSchemas {
+    pub inbound: RootSchema,
+    pub outbound: RootSchema,
+    pub continuation: RootSchema,
+}
+
+pub fn schemas() -> MessageSchemas {
+    let mut inbound = schema_for!(InboundMessage);
+    default_prove_type(&mut inbound);
+    MessageSchemas {
+        inbound,
+        outbound: schema_for!(Frame),
+        continuation: schema_for!(ContinuationFrame),
+    }
+}
+
+/// Marks `type` as optional on proof requests, which the host accepts without
+/// one, see `InboundRequest`.
+fn default_prove_type(inbound: &mut RootSchema) {
+    let variants = inbound
+        .schema
+        .subschemas
+        .as_mut()
+        .and_then(|subschemas| subschemas.one_of.as_mut());
+    for variant in variants.into_iter().flatten() {
+        let Schema::Object(variant) = variant else {
+            continue;
+        };
+        let Some(object) = variant.object.as_mut() else {
+            continue;
+        };
+        let Some(Schema::Object(tag)) = object.properties.get_mut("type") else {
+            continue;
+        };
+        let is_prove = tag
+            .enum_values
+            .as_ref()
+            .is_some_and(|values| values.iter().any(|value| value == "prove"));
+        if is_prove {
+            tag.metadata().default = Some("prove".into());
+            object.required.remove("type");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn checked_in_schema_is_up_to_date() {
+        let checked_in: Value =
+            serde_json::from_str(include_str!("../messages.schema.json")).unwrap();
+        assert_eq!(
+            serde_json::to_value(schemas()).unwrap(),
+            checked_in,
+            "messages.schema.json is stale, regenerate it with \
+             `krithon-prover schema --output messages.schema.json`"
+        );
+    }
+
+    #[test]
+    fn proof_requests_may_omit_type() {
+        let inbound = serde_json::to_value(schemas().inbound).unwrap();
+        let variants = inbound["oneOf"].as_array().unwrap();
+        let prove = variants
+            .iter()
+            .find(|variant| variant["properties"]["type"]["enum"][0] == "prove")
+            .unwrap();
+        assert!(!prove["required"]
+            .as_array()
+            .unwrap()
+            .contains(&"type".into()));
+        assert_eq!(prove["properties"]["type"]["default"], "prove");
+
+        // Every other message still needs its `type`.
+        for variant in variants.iter().filter(|variant| *variant != prove) {
+            assert!(variant["required"]
+                .as_array()
+                .unwrap()
+                .contains(&"type".into()));
+        }
+    }
+}
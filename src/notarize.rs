@@ -0,0 +1,91 @@
+use std::ops::Range;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
+use tlsn_core::{
+    attestation::Attestation, presentation::Presentation, request::RequestConfig,
+    transcript::TranscriptCommitConfig, CryptoProvider, Secrets,
+};
+use tlsn_prover::{state::Notarize, Prover};
+
+use crate::communication::{send_progress, Output};
+use crate::errors::Result;
+use crate::messages::Progress;
+
+/// Artifacts of a notarized session, serialized with bincode.
+///
+/// `attestation` and `secrets` together allow building further presentations
+/// later; `presentation` already discloses the ranges chosen by the policy.
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct Notarization {
+    #[serde(serialize_with = "as_base64")]
+    #[schemars(with = "String")]
+    pub attestation: Vec<u8>,
+    #[serde(serialize_with = "as_base64")]
+    #[schemars(with = "String")]
+    pub secrets: Vec<u8>,
+    #[serde(serialize_with = "as_base64")]
+    #[schemars(with = "String")]
+    pub presentation: Vec<u8>,
+}
+
+/// Commits to the revealed ranges, obtains a signed attestation from the
+/// notary and builds a presentation disclosing exactly those ranges.
+pub async fn notarize(
+    mut prover: Prover<Notarize>,
+    sent_ranges: &[Range<usize>],
+    recv_ranges: &[Range<usize>],
+    output: &mut Output,
+) -> Result<Notarization> {
+    let mut builder = TranscriptCommitConfig::builder(prover.transcript());
+    for range in sent_ranges {
+        builder.commit_sent(range)?;
+    }
+    for range in recv_ranges {
+        builder.commit_recv(range)?;
+    }
+    prover.transcript_commit(builder.build()?);
+
+    send_progress(Progress::TranscriptCommitted, output).await;
+
+    let (attestation, secrets) = prover.finalize(&RequestConfig::default()).await?;
+
+    let presentation = build_presentation(&attestation, &secrets, sent_ranges, recv_ranges)?;
+
+    Ok(Notarization {
+        attestation: bincode::serialize(&attestation)?,
+        secrets: bincode::serialize(&secrets)?,
+        presentation: bincode::serialize(&presentation)?,
+    })
+}
+
+/// Builds a selective-disclosure presentation from an attestation. The ranges
+/// must have been committed to when the attestation was requested.
+pub fn build_presentation(
+    attestation: &Attestation,
+    secrets: &Secrets,
+    sent_ranges: &[Range<usize>],
+    recv_ranges: &[Range<usize>],
+) -> Result<Presentation> {
+    let mut proof_builder = secrets.transcript_proof_builder();
+    for range in sent_ranges {
+        proof_builder.reveal_sent(range)?;
+    }
+    for range in recv_ranges {
+        proof_builder.reveal_recv(range)?;
+    }
+    let transcript_proof = proof_builder.build()?;
+
+    let provider = CryptoProvider::default();
+    let mut builder = attestation.presentation_builder(&provider);
+    builder
+        .identity_proof(secrets.identity_proof())
+        .transcript_proof(transcript_proof);
+
+    Ok(builder.build()?)
+}
+
+fn as_base64<S: Serializer>(bytes: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bytes))
+}
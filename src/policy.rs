@@ -0,0 +1,103 @@
+use clap::ArgAction;
+use hyper::header::HeaderName;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Errors, Result};
+use crate::keypath::Keypath;
+
+/// Describes which parts of the transcript are revealed to the verifier.
+/// Everything the policy does not match stays redacted.
+#[derive(clap::Args, Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(default)]
+pub struct RedactionPolicy {
+    /// JSON keypaths of the response body to reveal, e.g. `recipient.account`,
+    /// `transactions[0].amount`, `transactions[*].id` or `..id`. Prefix one
+    /// with `value:`, `key:` or `object:` to reveal only the value, only the
+    /// key, or the whole enclosing object instead of the key and value.
+    #[arg(long = "reveal-keypath")]
+    pub response_keypaths: Vec<String>,
+
+    /// JSON keypaths of the request body to reveal.
+    #[arg(long = "reveal-request-keypath")]
+    pub request_keypaths: Vec<String>,
+
+    /// Response header names to reveal, matched ignoring case. Names the
+    /// response lacks are listed in the proof outcome.
+    #[arg(long = "reveal-response-header")]
+    pub response_headers: Vec<String>,
+
+    /// Request header names to reveal, matched ignoring case.
+    #[arg(long = "reveal-request-header", default_value = "host")]
+    pub request_headers: Vec<String>,
+
+    /// Reveal the request line (method, target and protocol).
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub reveal_request_line: bool,
+
+    /// Reveal the response status line (protocol, status code and reason).
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    pub reveal_status_line: bool,
+
+    /// Which occurrences of a repeated header or JSON key to reveal.
+    #[arg(long = "reveal-duplicates", value_enum, default_value_t = Occurrence::Last)]
+    pub duplicates: Occurrence,
+}
+
+/// Picks among entries sharing a name, e.g. repeated `Set-Cookie` headers.
+#[derive(
+    clap::ValueEnum, Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Occurrence {
+    First,
+    /// The one a JSON parser keeps and a single-valued header lookup returns.
+    #[default]
+    Last,
+    All,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self {
+            response_keypaths: Vec::new(),
+            request_keypaths: Vec::new(),
+            response_headers: Vec::new(),
+            request_headers: vec!["host".to_string()],
+            reveal_request_line: true,
+            reveal_status_line: false,
+            duplicates: Occurrence::default(),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Checks the policy before any MPC work is done, so a typo in a keypath
+    /// or header name is reported instead of silently revealing nothing.
+    pub fn validate(&self) -> Result<()> {
+        parse_keypaths(&self.response_keypaths)?;
+        parse_keypaths(&self.request_keypaths)?;
+        for header in self.response_headers.iter().chain(&self.request_headers) {
+            validate_header_name(header)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_keypaths(keypaths: &[String]) -> Result<Vec<Keypath>> {
+    keypaths
+        .iter()
+        .map(|keypath| {
+            keypath
+                .parse()
+                .map_err(|e| Errors::InvalidPolicy(format!("Invalid keypath '{}': {}", keypath, e)))
+        })
+        .collect()
+}
+
+fn validate_header_name(header: &str) -> Result<()> {
+    header
+        .parse::<HeaderName>()
+        .map(|_| ())
+        .map_err(|e| Errors::InvalidPolicy(format!("Invalid header name '{}': {}", header, e)))
+}
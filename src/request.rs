@@ -1,8 +1,9 @@
-use crate::ast::{CommonParser, CommonRule, CommonRuleType, RangedHeader, RangedValue, Searchable};
+use crate::ast::{
+    CommonParser, CommonRule, CommonRuleType, MultiMap, RangedHeader, RangedValue, Searchable,
+};
 use pest::iterators::Pair;
 use pest::{iterators::Pairs, Parser};
 use pest_derive::Parser;
-use std::{collections::HashMap, ops::Range};
 
 #[derive(Parser)]
 #[grammar = "request.pest"]
@@ -11,7 +12,7 @@ pub struct RequestParser;
 #[derive(Debug)]
 pub struct Request {
     pub request_line: RangedHeader,
-    pub headers: HashMap<String, RangedHeader>,
+    pub headers: MultiMap<RangedHeader>,
     pub content: Option<RangedValue>,
 }
 
@@ -20,8 +21,8 @@ impl TryFrom<Pairs<'_, Rule>> for Request {
 
     fn try_from(pairs: Pairs<Rule>) -> Result<Self, Self::Error> {
         let mut request_line = None;
-        let mut headers = HashMap::new();
-        let mut content = RangedValue::default();
+        let mut headers = MultiMap::default();
+        let mut content = None;
 
         for pair in pairs {
             match pair.as_rule() {
@@ -34,10 +35,10 @@ impl TryFrom<Pairs<'_, Rule>> for Request {
                 }
                 Rule::header => {
                     let header = parse_request_header(pair)?;
-                    headers.insert(header.0, header.1);
+                    headers.push(header.0, header.1);
                 }
                 Rule::object | Rule::array => {
-                    content = parse_request_value(pair);
+                    content = Some(parse_request_value(pair)?);
                 }
                 _ => continue,
             }
@@ -46,7 +47,7 @@ impl TryFrom<Pairs<'_, Rule>> for Request {
         Ok(Self {
             request_line: request_line.ok_or("Missing request line")?,
             headers,
-            content: Some(content),
+            content,
         })
     }
 }
@@ -63,7 +64,7 @@ pub fn parse_request_header(pair: Pair<Rule>) -> Result<(String, RangedHeader),
 }
 
 /// Parses a `value` rule into a `RangedValue` for request.
-pub fn parse_request_value(pair: Pair<Rule>) -> RangedValue {
+pub fn parse_request_value(pair: Pair<Rule>) -> Result<RangedValue, &'static str> {
     CommonParser::parse_value(pair)
 }
 
@@ -82,15 +83,74 @@ impl CommonRule for Rule {
 }
 
 impl Searchable for Request {
-    fn get_headers(&self) -> &HashMap<String, RangedHeader> {
+    fn get_headers(&self) -> &MultiMap<RangedHeader> {
         &self.headers
     }
 
     fn get_content(&self) -> Option<&RangedValue> {
         self.content.as_ref()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypath::Keypath;
+    use crate::policy::Occurrence;
+
+    /// Transcript slices revealed by `keypaths` and `headers` in `transcript`.
+    fn revealed<'a>(transcript: &'a str, keypaths: &[&str], headers: &[&str]) -> Vec<&'a str> {
+        let request = parse_request(transcript).unwrap();
+        let keypaths: Vec<Keypath> = keypaths.iter().map(|k| k.parse().unwrap()).collect();
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        request
+            .get_all_ranges_for_keypaths(&keypaths, &headers, Occurrence::Last)
+            .into_iter()
+            .map(|range| &transcript[range])
+            .collect()
+    }
+
+    #[test]
+    fn reveals_keypaths_of_a_sent_json_body() {
+        let transcript = "POST /v1/search HTTP/1.1\r\n\
+                          host: api.example.com\r\n\
+                          content-type: application/json\r\n\
+                          content-length: 42\r\n\
+                          \r\n\
+                          {\"query\":\"rust\",\"filters\":{\"lang\":[\"en\"]}}";
+        let request = parse_request(transcript).unwrap();
+        assert_eq!(
+            &transcript[request.request_line.range.clone()],
+            "POST /v1/search HTTP/1.1\r\n"
+        );
+        assert!(request.content.is_some());
+
+        assert_eq!(
+            revealed(transcript, &["query", "value:filters.lang[0]"], &["Host"]),
+            vec!["host: api.example.com\r\n", "\"query\":\"rust\"", "\"en\""]
+        );
+    }
+
+    #[test]
+    fn reveals_keypaths_of_a_pretty_printed_body() {
+        let body = "{\n  \"query\" : \"rust\",\n  \"filters\": {\n    \"lang\": [ \"en\", \"de\" ]\n  }\n}\n";
+        let transcript = format!(
+            "PUT /v1/search HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
 
-    fn get_additional_ranges(&self) -> Vec<Range<usize>> {
-        vec![self.request_line.range.clone()]
+        assert_eq!(
+            revealed(
+                &transcript,
+                &["query", "filters.lang[1]", "key:filters"],
+                &[]
+            ),
+            vec!["\"query\" : \"rust\"", "\"de\"", "\"filters\""]
+        );
+        assert_eq!(
+            revealed(&transcript, &["object:filters.lang[0]"], &[]),
+            vec!["[ \"en\", \"de\" ]"]
+        );
     }
 }
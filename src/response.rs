@@ -1,8 +1,12 @@
-use crate::ast::{CommonParser, CommonRule, CommonRuleType, RangedHeader, RangedValue, Searchable};
+use crate::ast::{
+    CommonParser, CommonRule, CommonRuleType, MultiMap, RangedHeader, RangedValue, Searchable,
+};
+use crate::body::{decode_body, BodyMap};
+use crate::policy::Occurrence;
 use pest::iterators::Pair;
 use pest::{iterators::Pairs, Parser};
 use pest_derive::Parser;
-use std::collections::HashMap;
+use std::ops::Range;
 
 #[derive(Parser)]
 #[grammar = "response.pest"]
@@ -10,31 +14,53 @@ pub struct ResponseParser;
 
 #[derive(Debug)]
 pub struct Response {
-    pub headers: HashMap<String, RangedHeader>,
-    pub content: RangedValue,
+    pub status_line: RangedHeader,
+    pub headers: MultiMap<RangedHeader>,
+    /// Parsed body. Its ranges are offsets into the decoded body, see `body_map`.
+    pub content: Option<RangedValue>,
+    pub body_map: BodyMap,
 }
 
 impl TryFrom<Pairs<'_, Rule>> for Response {
     type Error = &'static str;
 
     fn try_from(pairs: Pairs<Rule>) -> Result<Self, Self::Error> {
-        let mut headers = HashMap::new();
-        let mut content = RangedValue::default();
+        let mut status_line = None;
+        let mut headers = MultiMap::default();
+        let mut content = None;
+        let mut body_map = BodyMap::default();
 
         for pair in pairs {
             match pair.as_rule() {
+                Rule::status_line => {
+                    let range = pair.as_span().start()..pair.as_span().end();
+                    status_line = Some(RangedHeader {
+                        range,
+                        value: pair.as_str().to_string(),
+                    });
+                }
                 Rule::header => {
                     let header = parse_response_header(pair)?;
-                    headers.insert(header.0, header.1);
+                    headers.push(header.0, header.1);
                 }
-                Rule::object | Rule::array => {
-                    content = parse_response_value(pair);
+                Rule::body => {
+                    let (body, map) =
+                        decode_body(pair.as_str().as_bytes(), pair.as_span().start(), &headers)?;
+                    if is_json_content(&headers) {
+                        content = parse_response_body(&body)?;
+                    }
+                    body_map = map;
                 }
                 _ => continue,
             }
         }
 
-        Ok(Self { headers, content })
+        Ok(Self {
+            status_line: status_line.ok_or("Missing status line")?,
+            headers,
+            content,
+            body_map,
+        })
     }
 }
 
@@ -44,13 +70,41 @@ pub fn parse_response(input: &str) -> Result<Response, &'static str> {
     Response::try_from(pairs)
 }
 
+/// Bodies declared as something other than JSON (e.g. an HTML redirect page)
+/// are kept out of the content instead of failing the whole parse.
+fn is_json_content(headers: &MultiMap<RangedHeader>) -> bool {
+    let content_type = headers.get_ignore_ascii_case("content-type", Occurrence::First);
+    content_type.first().is_none_or(|content_type| {
+        let mime = content_type
+            .value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim();
+        mime.eq_ignore_ascii_case("application/json")
+            || mime.to_ascii_lowercase().ends_with("+json")
+    })
+}
+
+/// Parses a decoded message body. An empty body has no content.
+fn parse_response_body(body: &[u8]) -> Result<Option<RangedValue>, &'static str> {
+    let body = std::str::from_utf8(body).map_err(|_| "Response body is not valid UTF-8")?;
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let mut pairs = ResponseParser::parse(Rule::json_body, body)
+        .map_err(|_| "Failed to parse response body")?;
+    pairs.next().map(parse_response_value).transpose()
+}
+
 /// Parses a `header` rule and returns its key-value pair with range.
 pub fn parse_response_header(pair: Pair<Rule>) -> Result<(String, RangedHeader), &'static str> {
     CommonParser::parse_header(pair)
 }
 
 /// Parses a `value` rule into a `RangedValue` for response.
-pub fn parse_response_value(pair: Pair<Rule>) -> RangedValue {
+pub fn parse_response_value(pair: Pair<Rule>) -> Result<RangedValue, &'static str> {
     CommonParser::parse_value(pair)
 }
 
@@ -69,11 +123,120 @@ impl CommonRule for Rule {
 }
 
 impl Searchable for Response {
-    fn get_headers(&self) -> &HashMap<String, RangedHeader> {
+    fn get_headers(&self) -> &MultiMap<RangedHeader> {
         &self.headers
     }
 
     fn get_content(&self) -> Option<&RangedValue> {
-        Some(&self.content)
+        self.content.as_ref()
+    }
+
+    fn map_content_range(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        self.body_map.to_transcript(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypath::Keypath;
+
+    /// Transcript slices revealed by `keypaths` and `headers` in `transcript`.
+    fn revealed<'a>(transcript: &'a str, keypaths: &[&str], headers: &[&str]) -> Vec<&'a str> {
+        let response = parse_response(transcript).unwrap();
+        let keypaths: Vec<Keypath> = keypaths.iter().map(|k| k.parse().unwrap()).collect();
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        response
+            .get_all_ranges_for_keypaths(&keypaths, &headers, Occurrence::Last)
+            .into_iter()
+            .map(|range| &transcript[range])
+            .collect()
+    }
+
+    #[test]
+    fn reveals_keypaths_of_a_chunked_body_in_the_transcript() {
+        let (first, second) = (r#"{"user":{"name":"Ada Lo"#, r#"velace","id":7}}"#);
+        let transcript = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: application/json\r\n\
+             Transfer-Encoding: chunked\r\n\
+             \r\n\
+             {:x};ext=1\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+
+        assert_eq!(
+            revealed(&transcript, &["user.name", "value:user.id"], &[]),
+            vec![r#""name":"Ada Lo"#, r#"velace""#, "7"]
+        );
+        assert_eq!(
+            revealed(&transcript, &["object:user.id"], &["Content-Type"]),
+            vec![
+                "Content-Type: application/json\r\n",
+                r#"{"name":"Ada Lo"#,
+                r#"velace","id":7}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_status_lines_with_and_without_reason_phrase() {
+        let response = parse_response("HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!(response.status_line.value, "HTTP/1.1 404 Not Found\r\n");
+        assert_eq!(response.status_line.range, 0..24);
+
+        let transcript = "HTTP/2.0 204\r\nServer: test\r\n\r\n";
+        let response = parse_response(transcript).unwrap();
+        assert_eq!(
+            &transcript[response.status_line.range.clone()],
+            "HTTP/2.0 204\r\n"
+        );
+        assert!(response.content.is_none());
+
+        assert!(parse_response("HTTP/1.1 20\r\n\r\n").is_err());
+        assert!(parse_response("HTTP/1.1 200OK\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn matches_header_names_ignoring_case() {
+        let transcript = "HTTP/1.1 200 OK\r\n\
+                          content-type: text/plain\r\n\
+                          \r\n";
+        assert_eq!(
+            revealed(transcript, &[], &["Content-Type"]),
+            vec!["content-type: text/plain\r\n"]
+        );
+
+        let response = parse_response(transcript).unwrap();
+        let headers = vec!["CONTENT-TYPE".to_string(), "X-Request-Id".to_string()];
+        assert_eq!(response.missing_headers(&headers), vec!["X-Request-Id"]);
+    }
+
+    #[test]
+    fn reveals_the_chosen_occurrence_of_repeated_headers() {
+        let transcript = "HTTP/1.1 200 OK\r\n\
+                          Set-Cookie: a=1\r\n\
+                          Date: today\r\n\
+                          set-cookie: b=2\r\n\
+                          \r\n";
+        let response = parse_response(transcript).unwrap();
+        let headers = vec!["Set-Cookie".to_string()];
+        let cookies = |occurrence| -> Vec<&str> {
+            response
+                .get_all_ranges_for_keypaths(&[], &headers, occurrence)
+                .into_iter()
+                .map(|range| &transcript[range])
+                .collect()
+        };
+
+        assert_eq!(cookies(Occurrence::First), vec!["Set-Cookie: a=1\r\n"]);
+        assert_eq!(cookies(Occurrence::Last), vec!["set-cookie: b=2\r\n"]);
+        assert_eq!(
+            cookies(Occurrence::All),
+            vec!["Set-Cookie: a=1\r\n", "set-cookie: b=2\r\n"]
+        );
     }
 }
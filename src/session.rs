@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::{
+    sync::Semaphore,
+    task::{AbortHandle, JoinError, JoinHandle},
+};
+
+use crate::errors::{Errors, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    /// Waiting for a free slot under the concurrency cap.
+    Queued,
+    Running,
+}
+
+impl std::fmt::Display for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::Queued => write!(f, "queued"),
+            SessionState::Running => write!(f, "running"),
+        }
+    }
+}
+
+struct SessionEntry {
+    state: SessionState,
+    handle: AbortHandle,
+    /// Tells apart sessions that reused the id of a cancelled one.
+    generation: u64,
+}
+
+/// Proving sessions started on behalf of the extension, keyed by request id.
+#[derive(Clone)]
+pub struct Sessions {
+    slots: Arc<Semaphore>,
+    running: Arc<Mutex<HashMap<String, SessionEntry>>>,
+    generations: Arc<AtomicU64>,
+}
+
+impl Sessions {
+    pub fn new(max_sessions: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_sessions)),
+            running: Default::default(),
+            generations: Default::default(),
+        }
+    }
+
+    /// Spawns the session `start` returns under `id`. The session is queued
+    /// until one of the `max_sessions` slots frees up and is forgotten once it
+    /// finishes, however it ends. It sends its terminal frame only if
+    /// `Finish::claim` succeeds. Should it panic, `on_panic` runs in its place
+    /// so the request still gets a terminal frame.
+    pub fn spawn<S, F, P>(&self, id: String, start: S, on_panic: P) -> Result<()>
+    where
+        S: FnOnce(Finish) -> F,
+        F: Future<Output = ()> + Send + 'static,
+        P: Future<Output = ()> + Send + 'static,
+    {
+        // Hold the lock until the entry is inserted, so a session finishing
+        // right away cannot try to remove itself before it was added.
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = running.get(&id) {
+            return Err(Errors::DuplicateRequestId {
+                id,
+                state: entry.state,
+            });
+        }
+
+        let slots = self.slots.clone();
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let finish = Finish {
+            sessions: self.clone(),
+            id: id.clone(),
+            generation,
+        };
+        let session = start(finish.clone());
+        let entry = EntryGuard(finish);
+        let handle = tokio::spawn(async move {
+            // The semaphore is never closed, so acquiring cannot fail.
+            if let Ok(_permit) = slots.acquire_owned().await {
+                entry
+                    .0
+                    .sessions
+                    .set_state(&entry.0.id, SessionState::Running);
+                // As a task of its own, a panicking session surfaces here as a
+                // `JoinError` instead of unwinding through the supervisor.
+                let result = AbortOnDrop::new(tokio::spawn(session)).await;
+                if result.is_err_and(|e| e.is_panic()) && entry.0.claim() {
+                    on_panic.await;
+                }
+            }
+        });
+
+        running.insert(
+            id,
+            SessionEntry {
+                state: SessionState::Queued,
+                handle: handle.abort_handle(),
+                generation,
+            },
+        );
+        Ok(())
+    }
+
+    /// Aborts the session running under `id`. Dropping its future tears down
+    /// everything the session owns, see `AbortOnDrop`. Succeeds only if the
+    /// session had not claimed its terminal frame yet, the caller then sends
+    /// `Cancelled` in its place.
+    pub fn cancel(&self, id: &str) -> Result<()> {
+        let entry = self
+            .lock()
+            .remove(id)
+            .ok_or_else(|| Errors::UnknownRequestId(id.to_string()))?;
+        entry.handle.abort();
+        Ok(())
+    }
+
+    /// Aborts every queued or running session that has not claimed its
+    /// terminal frame yet and returns their ids.
+    pub fn cancel_all(&self) -> Vec<String> {
+        self.lock()
+            .drain()
+            .map(|(id, entry)| {
+                entry.handle.abort();
+                id
+            })
+            .collect()
+    }
+
+    fn set_state(&self, id: &str, state: SessionState) {
+        if let Some(entry) = self.lock().get_mut(id) {
+            entry.state = state;
+        }
+    }
+
+    /// Removes the entry of the session started as `generation`, returning
+    /// whether it was still there.
+    fn remove(&self, id: &str, generation: u64) -> bool {
+        let mut running = self.lock();
+        let found = running
+            .get(id)
+            .is_some_and(|entry| entry.generation == generation);
+        if found {
+            running.remove(id);
+        }
+        found
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, SessionEntry>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The right of a session to send its terminal frame. A session and a
+/// cancellation race for it by removing the session's entry, so exactly one of
+/// them sends a terminal frame.
+#[derive(Clone)]
+pub struct Finish {
+    sessions: Sessions,
+    id: String,
+    generation: u64,
+}
+
+impl Finish {
+    /// Forgets the session and returns whether it was still registered, i.e.
+    /// whether the caller is the one to send the terminal frame.
+    pub fn claim(&self) -> bool {
+        self.sessions.remove(&self.id, self.generation)
+    }
+}
+
+/// Forgets a session once its task ends, whether it finished, panicked or was
+/// aborted.
+struct EntryGuard(Finish);
+
+impl Drop for EntryGuard {
+    fn drop(&mut self) {
+        self.0.claim();
+    }
+}
+
+/// Aborts a spawned task when dropped, so background tasks of a session do not
+/// outlive it when the session itself is cancelled.
+pub struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> AbortOnDrop<T> {
+    pub fn new(handle: JoinHandle<T>) -> Self {
+        Self(handle)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = std::result::Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_and_forgets_a_panicked_session() {
+        let sessions = Sessions::new(1);
+        let (reported, panicked) = oneshot::channel();
+        let session = |_| async { panic!("session bug") };
+        let on_panic = async move {
+            let _ = reported.send(());
+        };
+        sessions.spawn("a".to_string(), session, on_panic).unwrap();
+
+        panicked.await.expect("on_panic did not run");
+        settle().await;
+        assert!(sessions.lock().is_empty());
+        // The slot was released too.
+        let (done, finished) = oneshot::channel();
+        let session = |_| async move {
+            let _ = done.send(());
+        };
+        sessions.spawn("a".to_string(), session, async {}).unwrap();
+        finished.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_session_does_not_forget_its_successor() {
+        let sessions = Sessions::new(2);
+        sessions
+            .spawn("a".to_string(), |_| std::future::pending(), async {})
+            .unwrap();
+        settle().await;
+        sessions.cancel("a").unwrap();
+        sessions
+            .spawn("a".to_string(), |_| std::future::pending(), async {})
+            .unwrap();
+        settle().await;
+
+        let duplicate = sessions.spawn("a".to_string(), |_| async {}, async {});
+        assert!(matches!(duplicate, Err(Errors::DuplicateRequestId { .. })));
+    }
+
+    #[tokio::test]
+    async fn a_finished_session_cannot_be_cancelled() {
+        let sessions = Sessions::new(1);
+        let (claimed, finished) = oneshot::channel();
+        let (release, released) = oneshot::channel::<()>();
+        let session = |finish: Finish| async move {
+            let _ = claimed.send(finish.claim());
+            // Still sending the terminal frame when the cancel arrives.
+            let _ = released.await;
+        };
+        sessions.spawn("a".to_string(), session, async {}).unwrap();
+
+        assert!(finished.await.unwrap());
+        assert!(matches!(
+            sessions.cancel("a"),
+            Err(Errors::UnknownRequestId(_))
+        ));
+        assert!(sessions.cancel_all().is_empty());
+        let _ = release.send(());
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_session_loses_its_claim() {
+        let sessions = Sessions::new(1);
+        let (finish_tx, finish_rx) = oneshot::channel();
+        let session = |finish: Finish| {
+            let _ = finish_tx.send(finish);
+            std::future::pending()
+        };
+        sessions.spawn("a".to_string(), session, async {}).unwrap();
+        let finish = finish_rx.await.unwrap();
+        settle().await;
+
+        assert_eq!(sessions.cancel_all(), vec!["a".to_string()]);
+        assert!(!finish.claim());
+    }
+}
@@ -1,29 +1,87 @@
-use http_body_util::Empty;
-use hyper::{body::Bytes, Request as HyperRequest, StatusCode};
+use std::{
+    error::Error as StdError,
+    io,
+    ops::Range,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{body::Body, Request as HyperRequest};
 use hyper_util::rt::TokioIo;
 use pest::Parser;
 use pest_derive::Parser;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tlsn_common::config::ProtocolConfig;
 use tlsn_core::transcript::Idx;
-use tlsn_prover::{state::Prove, Prover, ProverConfig};
-use tokio::io::{AsyncRead, AsyncWrite, Stdout};
+use tlsn_prover::{Prover, ProverConfig};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::oneshot,
+};
 use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
 // use tracing::instrument;
 
 use crate::ast::Searchable;
-use crate::communication::logging_message;
-use crate::errors::Errors;
+use crate::bundle::{
+    unix_millis, ProtocolLimits, SessionBundle, TranscriptRecord, REDACTION_PLACEHOLDER,
+};
+use crate::communication::{logging_message, send_progress, Output};
+use crate::errors::{Direction, Errors, HandshakeStep, Stage};
+use crate::messages::Progress;
+use crate::notarize::{notarize, Notarization};
+use crate::policy::{parse_keypaths, RedactionPolicy};
 use crate::request::{Request, RequestParser, Rule as RequestRule};
 use crate::response::{Response, ResponseParser, Rule as ResponseRule};
+use crate::session::AbortOnDrop;
+use crate::Args;
+
+/// What a finished session produced besides the proof itself.
+#[derive(Serialize, JsonSchema, Debug, Default)]
+pub struct ProofOutcome {
+    /// Path of the session bundle, if `Args::bundle_dir` was set.
+    pub bundle: Option<PathBuf>,
+    /// Attestation artifacts, in notarization mode only.
+    pub notarization: Option<Notarization>,
+    /// Requested request headers the request lacked, left unrevealed.
+    pub missing_request_headers: Vec<String>,
+    /// Requested response headers the response lacked, left unrevealed.
+    pub missing_response_headers: Vec<String>,
+}
+
+/// How the transcript is handed over at the end of the session.
+#[derive(
+    clap::ValueEnum, Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofMode {
+    /// Prove the revealed ranges to an interactive verifier.
+    #[default]
+    Interactive,
+    /// Obtain a signed attestation from a notary and build a presentation from it.
+    Notarize,
+}
 
+/// Runs the whole MPC-TLS session for `request`.
 // #[instrument(skip(socket))]
-pub async fn prover<T: AsyncWrite + AsyncRead + Send + Unpin + 'static>(
+pub async fn prover<T, B>(
     socket: T,
-    request: HyperRequest<Empty<Bytes>>,
-    max_sent_data: usize,
-    max_recv_data: usize,
-    stdout: &mut Stdout,
-) -> Result<(), Errors> {
+    request: HyperRequest<B>,
+    args: &Args,
+    output: &mut Output,
+) -> Result<ProofOutcome, Errors>
+where
+    T: AsyncWrite + AsyncRead + Send + Unpin + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let started_at = unix_millis();
+    let policy = &args.policy;
+
+    output.set_stage(Stage::Setup);
+
     if request.uri().scheme().map(|s| s.as_str()) != Some("https") {
         return Err(Errors::InvalidScheme);
     }
@@ -36,7 +94,7 @@ pub async fn prover<T: AsyncWrite + AsyncRead + Send + Unpin + 'static>(
     let server_port = match request.uri().port_u16() {
         Some(port) => port,
         None => {
-            logging_message(stdout, "No port found, using default port 443").await;
+            logging_message(output, "No port found, using default port 443").await;
             443
         }
     };
@@ -48,118 +106,414 @@ pub async fn prover<T: AsyncWrite + AsyncRead + Send + Unpin + 'static>(
         .server_name(server_domain.as_str())
         .protocol_config(
             ProtocolConfig::builder()
-                .max_sent_data(max_sent_data)
-                .max_recv_data(max_recv_data)
+                .max_sent_data(args.max_sent_data)
+                .max_recv_data(args.max_recv_data)
                 .build()?,
         )
         .build()?;
 
     let prover = Prover::new(prover_config).setup(socket.compat()).await?;
 
-    logging_message(stdout, "Prover setup done").await;
+    send_progress(Progress::SetupDone, output).await;
 
     // Connect to TLS Server.
-    let tls_client_socket = tokio::net::TcpStream::connect((server_domain, server_port)).await?;
-
-    logging_message(stdout, "Connecting to TLS Server").await;
+    output.set_stage(Stage::ServerConnection);
+    let tls_client_socket = tokio::net::TcpStream::connect((server_domain.as_str(), server_port))
+        .await
+        .map_err(Errors::ServerConnectionError)?;
 
     // Pass server connection into the prover.
-    let (mpc_tls_connection, prover_fut) = prover.connect(tls_client_socket.compat()).await?;
+    let (mpc_tls_connection, prover_fut) = prover
+        .connect(tls_client_socket.compat())
+        .await
+        .map_err(handshake_failed(HandshakeStep::Connect))?;
 
-    logging_message(stdout, "Prover connected to TLS Server").await;
+    send_progress(Progress::ServerConnected, output).await;
 
     // Wrap the connection in a TokioIo compatibility layer to use it with hyper.
-    let mpc_tls_connection = TokioIo::new(mpc_tls_connection.compat());
+    let (request_written, mut written) = oneshot::channel();
+    let mpc_tls_connection = TokioIo::new(FlushSignal::new(
+        mpc_tls_connection.compat(),
+        request_written,
+    ));
 
-    logging_message(stdout, "Prover wrapped in TokioIo compatibility layer").await;
+    logging_message(output, "Prover wrapped in TokioIo compatibility layer").await;
     // Spawn the Prover to run in the background.
-    let prover_task = tokio::spawn(prover_fut);
+    let prover_task = AbortOnDrop::new(tokio::spawn(prover_fut));
 
-    logging_message(stdout, "Prover spawned").await;
+    logging_message(output, "Prover spawned").await;
 
     // MPC-TLS Handshake.
+    output.set_stage(Stage::Exchange);
     let (mut request_sender, connection) =
-        hyper::client::conn::http1::handshake(mpc_tls_connection).await?;
+        hyper::client::conn::http1::handshake(mpc_tls_connection)
+            .await
+            .map_err(handshake_failed(HandshakeStep::HttpHandshake))?;
 
-    logging_message(stdout, "MPC-TLS Handshake done").await;
+    logging_message(output, "MPC-TLS Handshake done").await;
 
     // Spawn the connection to run in the background.
-    tokio::spawn(connection);
+    let _connection_task = AbortOnDrop::new(tokio::spawn(connection));
+
+    logging_message(output, "Connection spawned").await;
+
+    // `send_request` only queues the request for the connection task, so
+    // wait for the first flush of the connection before reporting it as sent.
+    // The TLS handshake with the server runs once the request is written, so
+    // a failed exchange is reported as a failed handshake.
+    let mut response = std::pin::pin!(async {
+        request_sender
+            .send_request(request)
+            .await
+            .map_err(handshake_failed(HandshakeStep::Request))
+    });
+    let response = tokio::select! {
+        biased;
+        Ok(()) = &mut written => {
+            send_progress(Progress::RequestSent, output).await;
+            response.await?
+        }
+        response = &mut response => response?,
+    };
+
+    let status = response.status().as_u16();
+    send_progress(Progress::ResponseReceived { status }, output).await;
+
+    if !args.accepts_status(status) {
+        return Err(Errors::UnexpectedStatus {
+            status,
+            accepted: args.accepted_statuses.clone(),
+        });
+    }
+
+    let prover = prover_task.await??;
+
+    let mut outcome = ProofOutcome::default();
+    let (sent, received, sent_ranges, recv_ranges) = match args.mode {
+        ProofMode::Interactive => {
+            // Create proof for the Verifier.
+            let mut prover = prover.start_prove();
+
+            logging_message(output, "Prover started").await;
+
+            let sent = prover.transcript().sent().to_vec();
+            let received = prover.transcript().received().to_vec();
+
+            output.set_stage(Stage::Redaction);
+            let sent_ranges;
+            (sent_ranges, outcome.missing_request_headers) =
+                redact_and_reveal_sent_data(&sent, policy)?;
+            logging_message(output, "Sent data redacted and revealed").await;
 
-    logging_message(stdout, "Connection spawned").await;
+            let recv_ranges;
+            (recv_ranges, outcome.missing_response_headers) =
+                redact_and_reveal_received_data(&received, policy)?;
+            logging_message(output, "Received data redacted and revealed").await;
 
-    let response = request_sender.send_request(request).await?;
+            // Reveal parts of the transcript
+            output.set_stage(Stage::Proof);
+            prover
+                .prove_transcript(Idx::new(sent_ranges.clone()), Idx::new(recv_ranges.clone()))
+                .await?;
 
-    logging_message(stdout, "Request sent").await;
+            send_progress(Progress::TranscriptProved, output).await;
 
-    assert!(response.status() == StatusCode::OK);
+            // Finalize.
+            output.set_stage(Stage::Finalization);
+            prover.finalize().await?;
 
-    logging_message(stdout, "Response received is OK").await;
-    // Create proof for the Verifier.
-    let mut prover = prover_task.await??.start_prove();
+            send_progress(Progress::Finalized, output).await;
 
-    logging_message(stdout, "Prover started").await;
+            (sent, received, sent_ranges, recv_ranges)
+        }
+        ProofMode::Notarize => {
+            let prover = prover.start_notarize();
+
+            logging_message(output, "Notarization started").await;
 
-    let idx_sent = redact_and_reveal_sent_data(&mut prover).await?;
-    logging_message(stdout, "Sent data redacted and revealed").await;
+            let sent = prover.transcript().sent().to_vec();
+            let received = prover.transcript().received().to_vec();
 
-    let idx_recv = redact_and_reveal_received_data(&mut prover).await?;
-    logging_message(stdout, "Received data redacted and revealed").await;
+            output.set_stage(Stage::Redaction);
+            let (sent_ranges, recv_ranges);
+            (sent_ranges, outcome.missing_request_headers) =
+                redact_and_reveal_sent_data(&sent, policy)?;
+            (recv_ranges, outcome.missing_response_headers) =
+                redact_and_reveal_received_data(&received, policy)?;
+            logging_message(output, "Transcript ranges selected for commitment").await;
 
-    // Reveal parts of the transcript
-    prover.prove_transcript(idx_sent, idx_recv).await?;
+            output.set_stage(Stage::Finalization);
+            outcome.notarization =
+                Some(notarize(prover, &sent_ranges, &recv_ranges, output).await?);
 
-    logging_message(stdout, "Transcript proof done").await;
+            send_progress(Progress::Finalized, output).await;
+
+            (sent, received, sent_ranges, recv_ranges)
+        }
+    };
 
-    // Finalize.
-    prover.finalize().await?;
+    for (side, missing) in [
+        ("request", &outcome.missing_request_headers),
+        ("response", &outcome.missing_response_headers),
+    ] {
+        if !missing.is_empty() {
+            let message = format!(
+                "Headers not found in the {side}, left unrevealed: {}",
+                missing.join(", ")
+            );
+            logging_message(output, &message).await;
+        }
+    }
 
-    logging_message(stdout, "Prover finalized").await;
+    let Some(bundle_dir) = &args.bundle_dir else {
+        return Ok(outcome);
+    };
 
-    Ok(())
+    output.set_stage(Stage::Persistence);
+    let bundle = SessionBundle {
+        version: crate::bundle::BUNDLE_VERSION,
+        server_name: server_domain,
+        started_at,
+        finished_at: unix_millis(),
+        limits: ProtocolLimits {
+            max_sent_data: args.max_sent_data,
+            max_recv_data: args.max_recv_data,
+        },
+        redaction_placeholder: REDACTION_PLACEHOLDER as char,
+        sent: TranscriptRecord::new(&sent, &sent_ranges),
+        received: TranscriptRecord::new(&received, &recv_ranges),
+    };
+    let path = bundle.write_to(bundle_dir).await?;
+
+    logging_message(
+        output,
+        &format!("Session bundle written to {}", path.display()),
+    )
+    .await;
+
+    outcome.bundle = Some(path);
+    Ok(outcome)
 }
 
-/// Redacts and reveals received data to the verifier.
-async fn redact_and_reveal_received_data(
-    prover: &mut Prover<Prove>,
-) -> Result<Idx, Errors> {
-    let recv_transcript = prover.transcript().received();
+/// Wraps an error of the MPC-TLS connection to the server as a failed handshake at `step`.
+fn handshake_failed<E: std::fmt::Display>(step: HandshakeStep) -> impl FnOnce(E) -> Errors {
+    move |e| Errors::TlsHandshakeFailed {
+        step,
+        message: e.to_string(),
+    }
+}
 
+/// Computes the ranges of the received data revealed to the verifier, along
+/// with the requested response headers the response lacks.
+fn redact_and_reveal_received_data(
+    recv_transcript: &[u8],
+    policy: &RedactionPolicy,
+) -> Result<(Vec<Range<usize>>, Vec<String>), Errors> {
     let recv_string = String::from_utf8(recv_transcript.to_vec())?;
 
     let parse = ResponseParser::parse(ResponseRule::response, &recv_string)?;
 
-    let response = Response::try_from(parse).map_err(|e| Errors::StringError(e.to_string()))?;
-
-    let ranges = response.get_all_ranges_for_keypaths(
-        &[
-            "state",
-            "comment",
-            "currency",
-            "amount",
-            "recipient.account",
-            "recipient.username",
-            "recipient.code",
-            "beneficiary.account",
-        ],
-        &[],
+    let response = Response::try_from(parse).map_err(|e| Errors::TranscriptParseError {
+        direction: Direction::Received,
+        message: e.to_string(),
+    })?;
+
+    let keypaths = parse_keypaths(&policy.response_keypaths)?;
+    let mut ranges = response.get_all_ranges_for_keypaths(
+        &keypaths,
+        &policy.response_headers,
+        policy.duplicates,
     );
+    let missing = response.missing_headers(&policy.response_headers);
+    let requested = !policy.response_keypaths.is_empty() || !policy.response_headers.is_empty();
+    if requested && ranges.is_empty() {
+        return Err(Errors::PolicyMatchedNothing {
+            direction: Direction::Received,
+        });
+    }
+    if policy.reveal_status_line {
+        ranges.push(response.status_line.range.clone());
+    }
 
-    Ok(Idx::new(ranges))
+    check_ranges(&ranges, recv_transcript.len(), Direction::Received)?;
+    Ok((ranges, missing))
 }
 
-/// Redacts and reveals sent data to the verifier.
-async fn redact_and_reveal_sent_data(
-    prover: &mut Prover<Prove>,
-) -> Result<Idx, Errors> {
-    let sent_transcript = prover.transcript().sent();
-    let sent_string =  String::from_utf8(sent_transcript.to_vec())?;
+/// Computes the ranges of the sent data revealed to the verifier, along with
+/// the requested request headers the request lacks.
+fn redact_and_reveal_sent_data(
+    sent_transcript: &[u8],
+    policy: &RedactionPolicy,
+) -> Result<(Vec<Range<usize>>, Vec<String>), Errors> {
+    let sent_string = String::from_utf8(sent_transcript.to_vec())?;
 
     let parse = RequestParser::parse(RequestRule::request, &sent_string)?;
 
-    let request = Request::try_from(parse).map_err(|e| Errors::StringError(e.to_string()))?;
+    let request = Request::try_from(parse).map_err(|e| Errors::TranscriptParseError {
+        direction: Direction::Sent,
+        message: e.to_string(),
+    })?;
+
+    let keypaths = parse_keypaths(&policy.request_keypaths)?;
+    let mut ranges =
+        request.get_all_ranges_for_keypaths(&keypaths, &policy.request_headers, policy.duplicates);
+    let missing = request.missing_headers(&policy.request_headers);
+    let requested = !policy.request_keypaths.is_empty() || !policy.request_headers.is_empty();
+    if requested && ranges.is_empty() {
+        return Err(Errors::PolicyMatchedNothing {
+            direction: Direction::Sent,
+        });
+    }
+    if policy.reveal_request_line {
+        ranges.push(request.request_line.range.clone());
+    }
+
+    check_ranges(&ranges, sent_transcript.len(), Direction::Sent)?;
+    Ok((ranges, missing))
+}
+
+/// Makes sure every range to reveal lies within the transcript, so a bug in
+/// the range arithmetic fails the proof instead of revealing the wrong bytes.
+fn check_ranges(ranges: &[Range<usize>], len: usize, direction: Direction) -> Result<(), Errors> {
+    match ranges
+        .iter()
+        .find(|range| range.start > range.end || range.end > len)
+    {
+        Some(range) => Err(Errors::RangeOutOfBounds {
+            direction,
+            start: range.start,
+            end: range.end,
+            len,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Passes IO through to `inner` and fires `flushed` the first time written
+/// data is flushed, i.e. once hyper has pushed the request into the
+/// connection rather than just queued it.
+struct FlushSignal<T> {
+    inner: T,
+    wrote: bool,
+    flushed: Option<oneshot::Sender<()>>,
+}
+
+impl<T> FlushSignal<T> {
+    fn new(inner: T, flushed: oneshot::Sender<()>) -> Self {
+        Self {
+            inner,
+            wrote: false,
+            flushed: Some(flushed),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FlushSignal<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FlushSignal<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if matches!(written, Poll::Ready(Ok(n)) if n > 0) {
+            self.wrote = true;
+        }
+        written
+    }
 
-    let ranges = request.get_all_ranges_for_keypaths(&[], &["host"]);
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let flushed = Pin::new(&mut self.inner).poll_flush(cx);
+        if self.wrote && matches!(flushed, Poll::Ready(Ok(()))) {
+            if let Some(sender) = self.flushed.take() {
+                let _ = sender.send(());
+            }
+        }
+        flushed
+    }
 
-    Ok(Idx::new(ranges))
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reveals_the_status_line_on_request() {
+        let transcript = b"HTTP/1.1 204\r\nDate: today\r\n\r\n";
+        let policy = RedactionPolicy {
+            reveal_status_line: true,
+            ..Default::default()
+        };
+        let (ranges, _) = redact_and_reveal_received_data(transcript, &policy).unwrap();
+        assert_eq!(ranges, vec![0..14]);
+        assert_eq!(&transcript[0..14], b"HTTP/1.1 204\r\n");
+
+        let policy = RedactionPolicy::default();
+        let (ranges, _) = redact_and_reveal_received_data(transcript, &policy).unwrap();
+        assert!(ranges.is_empty());
+    }
+
+    #[test]
+    fn reports_missing_headers_and_reveals_the_rest() {
+        let transcript = b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\n\r\n";
+        let policy = RedactionPolicy {
+            response_headers: vec!["Content-Type".into(), "X-Request-Id".into()],
+            ..Default::default()
+        };
+        let (ranges, missing) = redact_and_reveal_received_data(transcript, &policy).unwrap();
+        assert_eq!(ranges, vec![17..43]);
+        assert_eq!(&transcript[17..43], b"content-type: text/plain\r\n");
+        assert_eq!(missing, vec!["X-Request-Id"]);
+
+        let policy = RedactionPolicy {
+            response_headers: vec!["X-Request-Id".into()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            redact_and_reveal_received_data(transcript, &policy),
+            Err(Errors::PolicyMatchedNothing {
+                direction: Direction::Received
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_transcript() {
+        assert!(check_ranges(&[0..4, 4..10], 10, Direction::Sent).is_ok());
+
+        let past_end = check_ranges(&[0..4, 8..11], 10, Direction::Received);
+        assert!(matches!(
+            past_end,
+            Err(Errors::RangeOutOfBounds {
+                direction: Direction::Received,
+                start: 8,
+                end: 11,
+                len: 10,
+            })
+        ));
+
+        let reversed = Range { start: 5, end: 3 };
+        let reversed = check_ranges(&[0..2, reversed], 10, Direction::Sent);
+        assert!(matches!(
+            reversed,
+            Err(Errors::RangeOutOfBounds {
+                start: 5,
+                end: 3,
+                ..
+            })
+        ));
+    }
 }